@@ -1,5 +1,4 @@
-use std::io;
-use std::io::Write;
+use mal::readline::*;
 
 fn read(s: String) -> String {
     s
@@ -17,20 +16,24 @@ fn rep(s: String) -> String {
     print(eval(read(s)))
 }
 
-fn repl_loop() {
-    print!("user> ");
-    io::stdout().flush().ok();
-
-    let mut input = String::new();
-    io::stdin().read_line(&mut input).expect("error reading line");
-
-    let output = rep(input);
-
-    print!("{}", output);
+fn repl_loop() -> bool {
+    match readline(PROMPT) {
+        Line::Read(input) => {
+            println!("{}", rep(input));
+            true
+        }
+        Line::Interrupted => true,
+        Line::Eof => {
+            println!();
+            false
+        }
+    }
 }
 
 fn main() {
     loop {
-        repl_loop();
+        if !repl_loop() {
+            break;
+        }
     }
 }
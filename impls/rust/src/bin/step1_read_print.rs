@@ -1,11 +1,10 @@
-use std::io;
-use std::io::Write;
-
+use mal::core::PPRINT_WIDTH;
+use mal::readline::*;
 use mal::types::*;
 
 fn read(s: String) -> MalResult<MalType> {
     let trimmed = s.trim();
-    mal::reader::read_str(&trimmed)
+    mal::reader::read_str(trimmed)
 }
 
 fn eval(expr: MalType) -> MalType {
@@ -13,7 +12,12 @@ fn eval(expr: MalType) -> MalType {
 }
 
 fn print(expr: MalType) -> String {
-    mal::printer::pr_str(expr, true)
+    // Results too long for one line are broken up when a person is reading them.
+    if is_interactive() {
+        mal::printer::pprint(&expr, PPRINT_WIDTH)
+    } else {
+        mal::printer::pr_str(&expr, true)
+    }
 }
 
 fn rep(s: String) -> MalResult<String> {
@@ -24,24 +28,32 @@ fn rep(s: String) -> MalResult<String> {
 }
 
 fn repl_loop() -> bool {
-    print!("user> ");
-    io::stdout().flush().ok();
-
+    // Keep reading lines until they add up to a complete form.
     let mut input = String::new();
-    io::stdin().read_line(&mut input).expect("error reading line");
+    loop {
+        let prompt = if input.is_empty() { PROMPT } else { CONTINUATION_PROMPT };
+        match readline(prompt) {
+            Line::Read(line) => {
+                input.push_str(&line);
+                input.push('\n');
+            }
+            // Drop what has been typed so far and prompt again.
+            Line::Interrupted => return true,
+            Line::Eof => {
+                println!();
+                return false;
+            }
+        }
 
-    if input.is_empty() {
-        println!();
-        return false;
-    }
+        match rep(input.clone()) {
+            Ok(output) => println!("{}", output),
+            Err(MalError::Empty) => (),
+            Err(MalError::Incomplete(_)) if is_interactive() => continue,
+            Err(e) => println!("error: {:?}", e),
+        }
 
-    match rep(input) {
-        Ok(output) => println!("{}", output),
-        Err(MalError::Empty) => (),
-        Err(e) => println!("error: {:?}", e),
+        return true;
     }
-
-    true
 }
 
 fn main() {
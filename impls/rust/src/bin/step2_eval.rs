@@ -1,12 +1,12 @@
-use std::io;
-use std::io::Write;
 use std::collections::HashMap;
 
+use mal::core::PPRINT_WIDTH;
+use mal::readline::*;
 use mal::types::*;
 
 fn read(s: String) -> MalResult<MalType> {
     let trimmed = s.trim();
-    mal::reader::read_str(&trimmed)
+    mal::reader::read_str(trimmed)
 }
 
 /// Simplifies an expression
@@ -17,7 +17,7 @@ fn eval_ast(expr: MalType, env: &HashMap<String, MalType>) -> MalType {
     match expr {
         // Look up a variable in environment
         MalType::Symbol(s) => {
-            env.get(&s).unwrap_or(&MalType::Nil).clone()
+            env.get(&*s.name()).unwrap_or(&MalType::Nil).clone()
         }
         // Simplify each element in a list
         MalType::List(list) => {
@@ -33,13 +33,13 @@ fn eval(expr: MalType, env: &HashMap<String, MalType>) -> MalType {
     if let MalType::List(list) = expr {
         if list.is_empty() {
             // Ownership question: it would be better to just return `expr` here.
-            MalType::List(Vec::new())
+            MalType::List(MalSeq::new())
         } else {
             let simplified = eval_ast(MalType::List(list), env);
             if let MalType::List(list) = simplified {
-                let op = list.get(0).unwrap();
-                if let MalType::Fun(f) = op {
-                    f(list[1..].to_vec()).unwrap()
+                let op = list.front().unwrap();
+                if let MalType::Fun(_, f) = op {
+                    f(list.skip(1).into_iter().collect()).unwrap()
                 } else {
                     MalType::List(list)
                 }
@@ -54,7 +54,12 @@ fn eval(expr: MalType, env: &HashMap<String, MalType>) -> MalType {
 }
 
 fn print(expr: MalType) -> String {
-    mal::printer::pr_str(expr, true)
+    // Results too long for one line are broken up when a person is reading them.
+    if is_interactive() {
+        mal::printer::pprint(&expr, PPRINT_WIDTH)
+    } else {
+        mal::printer::pr_str(&expr, true)
+    }
 }
 
 fn default_add(args: MalList) -> MalResult<MalType> {
@@ -68,7 +73,7 @@ fn default_add(args: MalList) -> MalResult<MalType> {
 fn default_sub(args: MalList) -> MalResult<MalType> {
     // So far assumes there are only two arguments, and panics otherwise.
     if args.len() == 2 {
-        if let Some(MalType::Int(i1)) = args.get(0) {
+        if let Some(MalType::Int(i1)) = args.first() {
             if let Some(MalType::Int(i2)) = args.get(1) {
                 return Ok(MalType::Int(i1 - i2));
             }
@@ -88,7 +93,7 @@ fn default_mul(args: MalList) -> MalResult<MalType> {
 fn default_div(args: MalList) -> MalResult<MalType> {
     // So far assumes there are only two arguments, and panics otherwise.
     if args.len() == 2 {
-        if let Some(MalType::Int(i1)) = args.get(0) {
+        if let Some(MalType::Int(i1)) = args.first() {
             if let Some(MalType::Int(i2)) = args.get(1) {
                 return Ok(MalType::Int(i1 / i2));
             }
@@ -100,10 +105,10 @@ fn default_div(args: MalList) -> MalResult<MalType> {
 fn rep(s: String) -> MalResult<String> {
     let mut env = HashMap::new();
     env.insert(String::from("foo"), MalType::Int(0));
-    env.insert(String::from("+"), MalType::Fun(default_add));
-    env.insert(String::from("-"), MalType::Fun(default_sub));
-    env.insert(String::from("*"), MalType::Fun(default_mul));
-    env.insert(String::from("/"), MalType::Fun(default_div));
+    env.insert(String::from("+"), MalType::Fun("+", default_add));
+    env.insert(String::from("-"), MalType::Fun("-", default_sub));
+    env.insert(String::from("*"), MalType::Fun("*", default_mul));
+    env.insert(String::from("/"), MalType::Fun("/", default_div));
 
     let a = read(s)?;
     let b = eval(a, &env);
@@ -112,24 +117,32 @@ fn rep(s: String) -> MalResult<String> {
 }
 
 fn repl_loop() -> bool {
-    print!("user> ");
-    io::stdout().flush().ok();
-
+    // Keep reading lines until they add up to a complete form.
     let mut input = String::new();
-    io::stdin().read_line(&mut input).expect("error reading line");
+    loop {
+        let prompt = if input.is_empty() { PROMPT } else { CONTINUATION_PROMPT };
+        match readline(prompt) {
+            Line::Read(line) => {
+                input.push_str(&line);
+                input.push('\n');
+            }
+            // Drop what has been typed so far and prompt again.
+            Line::Interrupted => return true,
+            Line::Eof => {
+                println!();
+                return false;
+            }
+        }
 
-    if input.is_empty() {
-        println!();
-        return false;
-    }
+        match rep(input.clone()) {
+            Ok(output) => println!("{}", output),
+            Err(MalError::Empty) => (),
+            Err(MalError::Incomplete(_)) if is_interactive() => continue,
+            Err(e) => println!("error: {:?}", e),
+        }
 
-    match rep(input) {
-        Ok(output) => println!("{}", output),
-        Err(MalError::Empty) => (),
-        Err(e) => println!("error: {:?}", e),
+        return true;
     }
-
-    true
 }
 
 fn main() {
@@ -1,143 +1,73 @@
-use std::io;
-use std::io::Write;
-
 use mal::env::*;
+use mal::eval::{eval, SPECIAL_FORMS};
+use mal::core::PPRINT_WIDTH;
+use mal::readline::*;
 use mal::types::*;
 
 fn read(s: String) -> MalResult<MalType> {
     let trimmed = s.trim();
-    mal::reader::read_str(&trimmed)
-}
-
-/// Simplifies an expression
-/// - looks up a symbol in the environment.
-/// - resolves each element in a list.
-fn eval_ast(expr: MalType, env: &mut MalEnv) -> MalResult<MalType> {
-    //println!("-- eval_ast() {:?}", expr);
-    match expr {
-        // Look up a variable in environment
-        MalType::Symbol(s) => {
-            if let Some(val) = env.get(&s) {
-                Ok(val)
-            } else {
-                Err(MalError::RuntimeError(format!("{} not found in environment", s)))
-            }
-        }
-        // Simplify each element in a list
-        MalType::List(list) => {
-            let mut acc = Vec::new();
-            for e in list {
-                acc.push(eval(e, env)?);
-            }
-            Ok(MalType::List(acc))
-        }
-        _ => Ok(expr),
-    }
+    mal::reader::read_str(trimmed)
 }
 
-fn update_env(key: &MalType, val: MalType, env: &mut MalEnv) -> MalResult<()>{
-    if let MalType::Symbol(s) = key {
-        env.set(String::from(s), val);
-        Ok(())
-    } else {
-        Err(MalError::RuntimeError(
-                format!("invalid def: {:?} = {:?}", key, val)))
-    }
-}
-
-// Resolve an expr to a final value.
-fn eval(expr: MalType, env: &mut MalEnv) -> MalResult<MalType> {
-    // Simplify list, then resolve by applying the function (first elt) to all other elts.
-    if let MalType::List(list) = expr {
-        if list.is_empty() {
-            // Ownership question: it would be better to just return `expr` here.
-            Ok(MalType::List(Vec::new()))
-        } else {
-            // Check first elt to see if it's a special.
-            let op = list.get(0).unwrap();
-            if let MalType::Symbol(s) = op {
-                match &s[..] {
-                    "def!" => {
-                        let arg1 = list.get(1).unwrap();
-                        let arg2 = list.get(2).unwrap();
-                        let val = eval(arg2.clone(), env)?;
-                        update_env(arg1, val.clone(), env)?;
-                        return Ok(val);
-                    },
-                    "let*" => {
-                        let mut inner = MalEnv::new(Some(env));
-                        let arg1 = list.get(1).unwrap();
-                        if let MalType::List(list) = arg1 {
-                            println!(">{:?}", list);
-                            for i in 0..(list.len()/2) {
-                                let arg1 = list.get(i*2).unwrap();
-                                let arg2 = list.get(i*2 + 1).unwrap();
-                                let val = eval(arg2.clone(), &mut inner)?;
-                                update_env(arg1, val.clone(), &mut inner)?;
-                            }
-                        }
-
-                        let final_expr = list.get(2).unwrap();
-                        return eval(final_expr.clone(), &mut inner)
-                    },
-                    _ => (),
-                }
-            }
-
-            let simplified = eval_ast(MalType::List(list), env)?;
-            if let MalType::List(list) = simplified {
-                let op = list.get(0).unwrap();
-                if let MalType::Fun(f) = op {
-                    f(list[1..].to_vec())
-                } else {
-                    Ok(MalType::List(list))
-                }
-            } else {
-                panic!("eval_ast(MalList) should always yield MalList");
-            }
-        }
+fn print(expr: MalType) -> String {
+    // Results too long for one line are broken up when a person is reading them.
+    if is_interactive() {
+        mal::printer::pprint(&expr, PPRINT_WIDTH)
     } else {
-        // All other types are resolved by eval_ast.
-        eval_ast(expr, env)
+        mal::printer::pr_str(&expr, true)
     }
 }
 
-fn print(expr: MalType) -> String {
-    mal::printer::pr_str(expr, true)
-}
-
-fn rep(s: String, env: &mut MalEnv) -> MalResult<String> {
+fn rep(s: String, env: &MalEnv) -> MalResult<String> {
+    mal::printer::set_limits(env.print_limits());
     let a = read(s)?;
     let b = eval(a, env)?;
+    // Realize as much of a lazy result as will be printed, so that errors raised while
+    // realizing it are reported.
+    if let MalType::LazySeq(_) = b {
+        let length = env.print_limits().length;
+        b.seq_iter().take(length.map_or(usize::MAX, |n| n + 1)).try_for_each(|e| e.map(drop))?;
+    }
     let c = print(b);
     Ok(c)
 }
 
-fn repl_loop(env: &mut MalEnv) -> bool {
-    print!("user> ");
-    io::stdout().flush().ok();
+fn repl_loop(env: &MalEnv) -> bool {
+    set_completions(env.symbols());
 
+    // Keep reading lines until they add up to a complete form.
     let mut input = String::new();
-    io::stdin().read_line(&mut input).expect("error reading line");
+    loop {
+        let prompt = if input.is_empty() { PROMPT } else { CONTINUATION_PROMPT };
+        match readline(prompt) {
+            Line::Read(line) => {
+                input.push_str(&line);
+                input.push('\n');
+            }
+            // Drop what has been typed so far and prompt again.
+            Line::Interrupted => return true,
+            Line::Eof => {
+                println!();
+                return false;
+            }
+        }
 
-    if input.is_empty() {
-        println!();
-        return false;
-    }
+        match rep(input.clone(), env) {
+            Ok(output) => println!("{}", output),
+            Err(MalError::Empty) => (),
+            Err(MalError::Incomplete(_)) if is_interactive() => continue,
+            Err(e) => println!("error: {:?}", e),
+        }
 
-    match rep(input, env) {
-        Ok(output) => println!("{}", output),
-        Err(MalError::Empty) => (),
-        Err(e) => println!("error: {:?}", e),
+        return true;
     }
-
-    true
 }
 
 fn main() {
-    let mut env = MalEnv::default();
+    let env = MalEnv::default();
+    set_special_forms(SPECIAL_FORMS);
     loop {
-        if !repl_loop(&mut env) {
+        if !repl_loop(&env) {
             break;
         }
     }
@@ -0,0 +1,1692 @@
+use std::cmp::Ordering;
+use std::convert::TryFrom;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::rc::Rc;
+
+use im_rc::{vector, HashMap, HashSet};
+use regex::{Captures, Match};
+use std::io::Write;
+
+use super::env::MalEnv;
+use super::eval::eval;
+use super::printer::{pprint, pr_str, write_expr, write_io};
+use super::reader::read_str;
+use super::readline::{readline, Line};
+use super::types::*;
+
+/// Line width used by `pprint` when none is given.
+pub const PPRINT_WIDTH: usize = 80;
+
+fn overflowed(name: &str, args: &[MalType]) -> MalError {
+    MalError::RuntimeError(format!("({}) overflowed: {}", name, args_str(args)))
+}
+
+fn default_add(args: MalList) -> MalResult<MalType> {
+    args.iter().try_fold(0i32, |acc, e| match e {
+        MalType::Int(i) => acc.checked_add(*i).ok_or_else(|| overflowed("+", &args)),
+        _ => Ok(acc),
+    }).map(MalType::Int)
+}
+
+fn default_sub(args: MalList) -> MalResult<MalType> {
+    // So far assumes there are only two arguments, and panics otherwise.
+    if args.len() == 2 {
+        if let Some(MalType::Int(i1)) = args.first() {
+            if let Some(MalType::Int(i2)) = args.get(1) {
+                return Ok(MalType::Int(i1 - i2));
+            }
+        }
+    }
+    Err(invalid_args("-", &args))
+}
+
+fn default_mul(args: MalList) -> MalResult<MalType> {
+    args.iter().try_fold(1i32, |acc, e| match e {
+        MalType::Int(i) => acc.checked_mul(*i).ok_or_else(|| overflowed("*", &args)),
+        _ => Ok(acc),
+    }).map(MalType::Int)
+}
+
+fn default_div(args: MalList) -> MalResult<MalType> {
+    // So far assumes there are only two arguments, and panics otherwise.
+    if args.len() == 2 {
+        if let Some(MalType::Int(i1)) = args.first() {
+            if let Some(MalType::Int(i2)) = args.get(1) {
+                return Ok(MalType::Int(i1 / i2));
+            }
+        }
+    }
+    Err(invalid_args("/", &args))
+}
+
+fn default_inc(args: MalList) -> MalResult<MalType> {
+    match args.as_slice() {
+        [MalType::Int(i)] => i.checked_add(1).map(MalType::Int)
+            .ok_or_else(|| MalError::RuntimeError(format!("(inc) overflowed: {}", i))),
+        _ => Err(invalid_args("inc", &args)),
+    }
+}
+
+fn default_dec(args: MalList) -> MalResult<MalType> {
+    match args.as_slice() {
+        [MalType::Int(i)] => i.checked_sub(1).map(MalType::Int)
+            .ok_or_else(|| MalError::RuntimeError(format!("(dec) overflowed: {}", i))),
+        _ => Err(invalid_args("dec", &args)),
+    }
+}
+
+fn default_symbol(args: MalList) -> MalResult<MalType> {
+    match args.as_slice() {
+        [MalType::Str(name)] => Ok(MalType::Symbol(MalSymbol::new(name))),
+        _ => Err(invalid_args("symbol", &args)),
+    }
+}
+
+fn default_identity(args: MalList) -> MalResult<MalType> {
+    if args.len() == 1 {
+        Ok(args.into_iter().next().unwrap())
+    } else {
+        Err(invalid_args("identity", &args))
+    }
+}
+
+/// Checks that each pair of adjacent integer arguments satisfies `test`.
+fn compare_ints(name: &str, args: &[MalType], test: fn(&i32, &i32) -> bool)
+    -> MalResult<MalType> {
+    let mut ints = Vec::with_capacity(args.len());
+    for e in args {
+        match e {
+            MalType::Int(i) => ints.push(*i),
+            _ => return Err(invalid_args(name, args)),
+        }
+    }
+    if ints.is_empty() {
+        return Err(invalid_args(name, args));
+    }
+    Ok(MalType::Bool(ints.windows(2).all(|w| test(&w[0], &w[1]))))
+}
+
+fn default_lt(args: MalList) -> MalResult<MalType> {
+    compare_ints("<", &args, i32::lt)
+}
+
+fn default_le(args: MalList) -> MalResult<MalType> {
+    compare_ints("<=", &args, i32::le)
+}
+
+fn default_gt(args: MalList) -> MalResult<MalType> {
+    compare_ints(">", &args, i32::gt)
+}
+
+fn default_ge(args: MalList) -> MalResult<MalType> {
+    compare_ints(">=", &args, i32::ge)
+}
+
+fn default_read_string(args: MalList) -> MalResult<MalType> {
+    match args.first() {
+        Some(MalType::Str(s)) => match read_str(s) {
+            Err(MalError::Empty) => Ok(MalType::Nil),
+            result => result,
+        },
+        _ => Err(invalid_args("read-string", &args)),
+    }
+}
+
+/// Evaluates a form in `env`, the environment `eval` was bound in, as if typed at the REPL.
+fn default_eval(env: &MalEnv, args: MalList) -> MalResult<MalType> {
+    match args.as_slice() {
+        [form] => eval(form.clone(), env),
+        _ => Err(invalid_args("eval", &args)),
+    }
+}
+
+fn default_slurp(args: MalList) -> MalResult<MalType> {
+    match args.first() {
+        Some(MalType::Str(path)) => fs::read_to_string(&**path)
+            .map(|s| MalType::Str(s.into()))
+            .map_err(|e| MalError::RuntimeError(format!("(slurp) can't read {}: {}", path, e))),
+        _ => Err(invalid_args("slurp", &args)),
+    }
+}
+
+fn default_readline(args: MalList) -> MalResult<MalType> {
+    if let Some(MalType::Str(prompt)) = args.first() {
+        match readline(prompt) {
+            Line::Read(line) => Ok(MalType::Str(line.into())),
+            Line::Interrupted | Line::Eof => Ok(MalType::Nil),
+        }
+    } else {
+        Err(invalid_args("readline", &args))
+    }
+}
+
+/// Writes each argument with `print_readably`, separated by `sep`.
+fn write_args<W: fmt::Write>(out: &mut W, args: &[MalType], print_readably: bool, sep: &str)
+    -> fmt::Result {
+    for (i, e) in args.iter().enumerate() {
+        if i > 0 {
+            out.write_str(sep)?;
+        }
+        write_expr(out, e, print_readably)?;
+    }
+    Ok(())
+}
+
+fn join_args(args: &[MalType], print_readably: bool, sep: &str) -> String {
+    let mut acc = String::new();
+    write_args(&mut acc, args, print_readably, sep).expect("writing to a String cannot fail");
+    acc
+}
+
+fn print_args(args: &[MalType], print_readably: bool) -> MalResult<MalType> {
+    let mut out = io::stdout().lock();
+    for (i, e) in args.iter().enumerate() {
+        if i > 0 {
+            write!(out, " ").ok();
+        }
+        write_io(&mut out, e, print_readably).ok();
+    }
+    writeln!(out).ok();
+    Ok(MalType::Nil)
+}
+
+fn default_pr_str(args: MalList) -> MalResult<MalType> {
+    Ok(MalType::Str(join_args(&args, true, " ").into()))
+}
+
+fn default_str(args: MalList) -> MalResult<MalType> {
+    Ok(MalType::Str(join_args(&args, false, "").into()))
+}
+
+fn default_prn(args: MalList) -> MalResult<MalType> {
+    print_args(&args, true)
+}
+
+fn default_println(args: MalList) -> MalResult<MalType> {
+    print_args(&args, false)
+}
+
+fn default_atom(args: MalList) -> MalResult<MalType> {
+    if let Some(val) = args.into_iter().next() {
+        Ok(MalType::Atom(MalAtom::new(val)))
+    } else {
+        Err(MalError::RuntimeError("(atom) needs a value".to_string()))
+    }
+}
+
+fn default_is_atom(args: MalList) -> MalResult<MalType> {
+    Ok(MalType::Bool(matches!(args.first(), Some(MalType::Atom(_)))))
+}
+
+fn default_deref(args: MalList) -> MalResult<MalType> {
+    if let Some(MalType::Atom(atom)) = args.first() {
+        Ok(atom.0.borrow().clone())
+    } else {
+        Err(invalid_args("deref", &args))
+    }
+}
+
+fn default_reset(args: MalList) -> MalResult<MalType> {
+    if let (Some(MalType::Atom(atom)), Some(val)) = (args.first(), args.get(1)) {
+        *atom.0.borrow_mut() = val.clone();
+        Ok(val.clone())
+    } else {
+        Err(invalid_args("reset!", &args))
+    }
+}
+
+fn default_swap(args: MalList) -> MalResult<MalType> {
+    if let (Some(MalType::Atom(atom)), Some(f)) = (args.first(), args.get(1)) {
+        let mut fargs = vec![atom.0.borrow().clone()];
+        fargs.extend_from_slice(&args[2..]);
+        let val = f.apply(fargs)?;
+        *atom.0.borrow_mut() = val.clone();
+        Ok(val)
+    } else {
+        Err(invalid_args("swap!", &args))
+    }
+}
+
+fn default_char(args: MalList) -> MalResult<MalType> {
+    match args.first() {
+        Some(MalType::Char(c)) => Ok(MalType::Char(*c)),
+        Some(MalType::Int(i)) => {
+            u32::try_from(*i).ok().and_then(char::from_u32).map(MalType::Char)
+                .ok_or_else(|| MalError::RuntimeError(format!("(char) invalid code point: {}", i)))
+        }
+        _ => Err(invalid_args("char", &args)),
+    }
+}
+
+fn default_int(args: MalList) -> MalResult<MalType> {
+    match args.first() {
+        Some(MalType::Int(i)) => Ok(MalType::Int(*i)),
+        Some(MalType::Char(c)) => Ok(MalType::Int(*c as i32)),
+        _ => Err(invalid_args("int", &args)),
+    }
+}
+
+fn default_seq(args: MalList) -> MalResult<MalType> {
+    let items = match args.into_iter().next() {
+        Some(MalType::List(l)) | Some(MalType::Vector(l)) => l,
+        Some(MalType::Str(s)) => s.chars().map(MalType::Char).collect(),
+        Some(MalType::Set(s)) => s.set.into_iter().collect(),
+        Some(MalType::SortedSet(s)) => s.set.into_iter().map(|k| k.key).collect(),
+        Some(MalType::HashMap(m)) => {
+            m.map.into_iter().map(|(k, v)| MalType::Vector(vector![k, v])).collect()
+        }
+        Some(MalType::SortedMap(m)) => {
+            m.map.into_iter().map(|(k, v)| MalType::Vector(vector![k.key, v])).collect()
+        }
+        Some(MalType::Nil) => MalSeq::new(),
+        Some(MalType::LazySeq(seq)) => {
+            return Ok(if seq.realize()?.is_some() { MalType::LazySeq(seq) } else { MalType::Nil });
+        }
+        other => return Err(invalid_args("seq", &other.into_iter().collect::<Vec<_>>())),
+    };
+    if items.is_empty() {
+        Ok(MalType::Nil)
+    } else {
+        Ok(MalType::List(items))
+    }
+}
+
+fn default_list(args: MalList) -> MalResult<MalType> {
+    Ok(MalType::List(args.into()))
+}
+
+fn default_is_list(args: MalList) -> MalResult<MalType> {
+    Ok(MalType::Bool(matches!(args.first(), Some(MalType::List(_)))))
+}
+
+pub(crate) fn default_is_nil(args: MalList) -> MalResult<MalType> {
+    Ok(MalType::Bool(matches!(args.first(), Some(MalType::Nil))))
+}
+
+fn default_is_empty(args: MalList) -> MalResult<MalType> {
+    match args.as_slice() {
+        [coll] => Ok(MalType::Bool(coll.first_rest()?.is_none())),
+        _ => Err(invalid_args("empty?", &args)),
+    }
+}
+
+/// `(concat & colls)` is a list of the elements of each of `colls` in turn. Unlike `cons`, it
+/// realizes lazy seqs.
+fn default_concat(args: MalList) -> MalResult<MalType> {
+    let mut acc = MalSeq::new();
+    for coll in &args {
+        for e in coll.seq_iter() {
+            acc.push_back(e?);
+        }
+    }
+    Ok(MalType::List(acc))
+}
+
+fn default_count(args: MalList) -> MalResult<MalType> {
+    let n = match args.first() {
+        Some(MalType::List(l)) | Some(MalType::Vector(l)) => l.len(),
+        Some(MalType::Str(s)) => s.chars().count(),
+        Some(MalType::HashMap(m)) => m.map.len(),
+        Some(MalType::Set(s)) => s.set.len(),
+        Some(MalType::SortedMap(m)) => m.map.len(),
+        Some(MalType::SortedSet(s)) => s.set.len(),
+        Some(MalType::Nil) => 0,
+        Some(seq @ MalType::LazySeq(_)) => {
+            seq.seq_iter().try_fold(0, |n, e| e.map(|_| n + 1))?
+        }
+        _ => return Err(invalid_args("count", &args)),
+    };
+    i32::try_from(n).map(MalType::Int).map_err(|_| invalid_args("count", &args))
+}
+
+/// `(nth coll i)` is the element at index `i`, and an error if there is none.
+/// `(nth coll i not-found)` returns `not-found` instead.
+fn default_nth(args: MalList) -> MalResult<MalType> {
+    let found = match (args.first(), args.get(1)) {
+        (Some(coll), Some(MalType::Int(i))) if (2..=3).contains(&args.len()) => {
+            match usize::try_from(*i) {
+                Ok(i) => coll.seq_iter().nth(i).transpose()?,
+                Err(_) => None,
+            }
+        }
+        _ => return Err(invalid_args("nth", &args)),
+    };
+    found.or_else(|| args.get(2).cloned()).ok_or_else(|| {
+        MalError::RuntimeError(format!("(nth) index out of bounds: {}", args_str(&args)))
+    })
+}
+
+fn default_eq(args: MalList) -> MalResult<MalType> {
+    Ok(MalType::Bool(args.windows(2).all(|w| w[0] == w[1])))
+}
+
+/// Prints arguments for an error message, readably and as a list.
+fn args_str(args: &[MalType]) -> String {
+    pr_str(&MalType::List(args.iter().cloned().collect()), true)
+}
+
+pub(crate) fn invalid_args(name: &str, args: &[MalType]) -> MalError {
+    MalError::RuntimeError(format!("({}) has invalid arguments: {}", name, args_str(args)))
+}
+
+/// Takes the elements of a list, vector, set or nil.
+fn into_items(name: &str, coll: MalType) -> MalResult<MalSeq> {
+    match coll {
+        MalType::List(l) | MalType::Vector(l) => Ok(l),
+        MalType::Set(s) => Ok(s.set.into_iter().collect()),
+        MalType::SortedSet(s) => Ok(s.set.into_iter().map(|k| k.key).collect()),
+        MalType::Nil => Ok(MalSeq::new()),
+        seq @ MalType::LazySeq(_) => seq.seq_iter().collect(),
+        other => Err(invalid_args(name, &[other])),
+    }
+}
+
+/// Takes the elements of a set, sorted or not, for the set operations. Their results are always
+/// hash sets.
+fn into_set(name: &str, coll: MalType) -> MalResult<HashSet<MalType>> {
+    match coll {
+        MalType::Set(s) => Ok(s.set),
+        MalType::SortedSet(s) => Ok(s.set.into_iter().map(|k| k.key).collect()),
+        other => Err(invalid_args(name, &[other])),
+    }
+}
+
+fn default_hash_set(args: MalList) -> MalResult<MalType> {
+    Ok(MalType::Set(MalSet { set: args.into_iter().collect() }))
+}
+
+fn default_set(args: MalList) -> MalResult<MalType> {
+    let coll = args.into_iter().next().unwrap_or(MalType::Nil);
+    Ok(MalType::Set(MalSet { set: into_items("set", coll)?.into_iter().collect() }))
+}
+
+fn default_is_set(args: MalList) -> MalResult<MalType> {
+    Ok(MalType::Bool(matches!(args.first(), Some(MalType::Set(_)))))
+}
+
+fn default_conj(args: MalList) -> MalResult<MalType> {
+    let mut args = args.into_iter();
+    match args.next() {
+        Some(MalType::List(mut l)) => {
+            // Lists grow at the front.
+            for e in args {
+                l.push_front(e);
+            }
+            Ok(MalType::List(l))
+        }
+        Some(MalType::Vector(mut v)) => {
+            v.extend(args);
+            Ok(MalType::Vector(v))
+        }
+        Some(MalType::Set(mut s)) => {
+            s.set.extend(args);
+            Ok(MalType::Set(s))
+        }
+        Some(MalType::SortedSet(mut s)) => {
+            for e in args {
+                s.insert(e)?;
+            }
+            Ok(MalType::SortedSet(s))
+        }
+        Some(MalType::HashMap(mut m)) => {
+            for e in args {
+                match e {
+                    MalType::Vector(mut kv) if kv.len() == 2 => {
+                        let v = kv.pop_back().unwrap();
+                        let k = kv.pop_back().unwrap();
+                        m.map.insert(k, v);
+                    }
+                    other => return Err(invalid_args("conj", &[other])),
+                }
+            }
+            Ok(MalType::HashMap(m))
+        }
+        Some(MalType::SortedMap(mut m)) => {
+            for e in args {
+                match e {
+                    MalType::Vector(mut kv) if kv.len() == 2 => {
+                        let v = kv.pop_back().unwrap();
+                        let k = kv.pop_back().unwrap();
+                        m.insert(k, v)?;
+                    }
+                    other => return Err(invalid_args("conj", &[other])),
+                }
+            }
+            Ok(MalType::SortedMap(m))
+        }
+        Some(MalType::Nil) => Ok(MalType::List(args.rev().collect())),
+        other => Err(invalid_args("conj", &other.into_iter().collect::<Vec<_>>())),
+    }
+}
+
+fn default_disj(args: MalList) -> MalResult<MalType> {
+    let mut args = args.into_iter();
+    match args.next() {
+        Some(MalType::SortedSet(mut s)) => {
+            for e in args {
+                s.remove(&e)?;
+            }
+            Ok(MalType::SortedSet(s))
+        }
+        coll => {
+            let mut set = into_set("disj", coll.unwrap_or(MalType::Nil))?;
+            for e in args {
+                set.remove(&e);
+            }
+            Ok(MalType::Set(MalSet { set }))
+        }
+    }
+}
+
+fn default_contains(args: MalList) -> MalResult<MalType> {
+    match (args.first(), args.get(1)) {
+        (Some(MalType::Set(s)), Some(key)) => Ok(MalType::Bool(s.set.contains(key))),
+        (Some(MalType::HashMap(m)), Some(key)) => Ok(MalType::Bool(m.map.contains_key(key))),
+        (Some(MalType::SortedSet(s)), Some(key)) => Ok(MalType::Bool(s.contains(key)?)),
+        (Some(MalType::SortedMap(m)), Some(key)) => Ok(MalType::Bool(m.get(key)?.is_some())),
+        (Some(MalType::Vector(v)), Some(MalType::Int(i))) => {
+            Ok(MalType::Bool(*i >= 0 && (*i as usize) < v.len()))
+        }
+        (Some(MalType::Nil), Some(_)) => Ok(MalType::Bool(false)),
+        _ => Err(invalid_args("contains?", &args)),
+    }
+}
+
+fn default_union(args: MalList) -> MalResult<MalType> {
+    let mut acc = HashSet::new();
+    for s in args {
+        acc.extend(into_set("union", s)?);
+    }
+    Ok(MalType::Set(MalSet { set: acc }))
+}
+
+fn default_intersection(args: MalList) -> MalResult<MalType> {
+    let mut args = args.into_iter();
+    let mut acc = into_set("intersection", args.next().unwrap_or(MalType::Nil))?;
+    for s in args {
+        let s = into_set("intersection", s)?;
+        acc.retain(|e| s.contains(e));
+    }
+    Ok(MalType::Set(MalSet { set: acc }))
+}
+
+fn default_difference(args: MalList) -> MalResult<MalType> {
+    let mut args = args.into_iter();
+    let mut acc = into_set("difference", args.next().unwrap_or(MalType::Nil))?;
+    for s in args {
+        let s = into_set("difference", s)?;
+        acc.retain(|e| !s.contains(e));
+    }
+    Ok(MalType::Set(MalSet { set: acc }))
+}
+
+fn default_is_subset(args: MalList) -> MalResult<MalType> {
+    if args.len() != 2 {
+        return Err(invalid_args("subset?", &args));
+    }
+    let mut args = args.into_iter();
+    let a = into_set("subset?", args.next().unwrap())?;
+    let b = into_set("subset?", args.next().unwrap())?;
+    Ok(MalType::Bool(a.is_subset(&b)))
+}
+
+/// Takes the comparator given to `sorted-map-by` or `sorted-set-by`, which can be anything
+/// callable.
+fn comparator(name: &str, f: Option<MalType>) -> MalResult<Option<Rc<MalType>>> {
+    match f {
+        Some(f) if f.is_callable() => Ok(Some(Rc::new(f))),
+        other => Err(invalid_args(name, &other.into_iter().collect::<Vec<_>>())),
+    }
+}
+
+fn sorted_map(name: &str, comparator: Option<Rc<MalType>>, args: MalList) -> MalResult<MalType> {
+    if !args.len().is_multiple_of(2) {
+        return Err(invalid_args(name, &args));
+    }
+    let mut map = MalSortedMap::new(comparator);
+    let mut args = args.into_iter();
+    while let (Some(k), Some(v)) = (args.next(), args.next()) {
+        map.insert(k, v)?;
+    }
+    Ok(MalType::SortedMap(map))
+}
+
+fn sorted_set(comparator: Option<Rc<MalType>>, args: MalList) -> MalResult<MalType> {
+    let mut set = MalSortedSet::new(comparator);
+    for e in args {
+        set.insert(e)?;
+    }
+    Ok(MalType::SortedSet(set))
+}
+
+fn default_sorted_map(args: MalList) -> MalResult<MalType> {
+    sorted_map("sorted-map", None, args)
+}
+
+fn default_sorted_map_by(args: MalList) -> MalResult<MalType> {
+    let mut args = args.into_iter();
+    let comparator = comparator("sorted-map-by", args.next())?;
+    sorted_map("sorted-map-by", comparator, args.collect())
+}
+
+fn default_sorted_set(args: MalList) -> MalResult<MalType> {
+    sorted_set(None, args)
+}
+
+fn default_sorted_set_by(args: MalList) -> MalResult<MalType> {
+    let mut args = args.into_iter();
+    let comparator = comparator("sorted-set-by", args.next())?;
+    sorted_set(comparator, args.collect())
+}
+
+/// Entries of a sorted collection that satisfy the one or two tests in `args`, which is
+/// `(coll test key)` or `(coll start-test start-key end-test end-key)`. The tests must be the
+/// builtins `<`, `<=`, `>` or `>=`.
+fn sorted_range(name: &str, args: &[MalType]) -> MalResult<MalList> {
+    let mut tests = Vec::new();
+    for pair in args.get(1..).unwrap_or_default().chunks(2) {
+        match pair {
+            [MalType::Fun(test, _), key] => tests.push((*test, key.clone())),
+            _ => return Err(invalid_args(name, args)),
+        }
+    }
+    match args.first() {
+        Some(MalType::SortedMap(m)) if !tests.is_empty() && tests.len() <= 2 => m.range(&tests),
+        Some(MalType::SortedSet(s)) if !tests.is_empty() && tests.len() <= 2 => s.range(&tests),
+        _ => Err(invalid_args(name, args)),
+    }
+}
+
+fn default_subseq(args: MalList) -> MalResult<MalType> {
+    let items = sorted_range("subseq", &args)?;
+    Ok(if items.is_empty() { MalType::Nil } else { MalType::List(items.into()) })
+}
+
+fn default_rsubseq(args: MalList) -> MalResult<MalType> {
+    let mut items = sorted_range("rsubseq", &args)?;
+    items.reverse();
+    Ok(if items.is_empty() { MalType::Nil } else { MalType::List(items.into()) })
+}
+
+fn default_get(args: MalList) -> MalResult<MalType> {
+    let found = match (args.first(), args.get(1)) {
+        (Some(coll), Some(key)) if args.len() <= 3 => coll.get(key)?,
+        _ => return Err(invalid_args("get", &args)),
+    };
+    Ok(found.or_else(|| args.get(2).cloned()).unwrap_or(MalType::Nil))
+}
+
+fn default_assoc(args: MalList) -> MalResult<MalType> {
+    if args.len().is_multiple_of(2) {
+        return Err(invalid_args("assoc", &args));
+    }
+    let mut args = args.into_iter();
+    let mut coll = args.next().unwrap_or(MalType::Nil);
+    while let (Some(k), Some(v)) = (args.next(), args.next()) {
+        match &mut coll {
+            MalType::HashMap(m) => { m.map.insert(k, v); }
+            MalType::SortedMap(m) => m.insert(k, v)?,
+            _ => return Err(invalid_args("assoc", &[coll])),
+        }
+    }
+    Ok(coll)
+}
+
+fn default_dissoc(args: MalList) -> MalResult<MalType> {
+    let mut args = args.into_iter();
+    let mut coll = args.next().unwrap_or(MalType::Nil);
+    for k in args {
+        match &mut coll {
+            MalType::HashMap(m) => { m.map.remove(&k); }
+            MalType::SortedMap(m) => m.remove(&k)?,
+            _ => return Err(invalid_args("dissoc", &[coll])),
+        }
+    }
+    Ok(coll)
+}
+
+fn lazy(thunk: impl FnOnce() -> MalResult<MalType> + 'static) -> MalType {
+    MalType::LazySeq(MalLazySeq::new(thunk))
+}
+
+/// Puts `first` in front of the seqable `rest` without realizing it.
+fn cons(first: MalType, rest: MalType) -> MalType {
+    MalType::LazySeq(MalLazySeq::cons(first, rest))
+}
+
+fn default_cons(args: MalList) -> MalResult<MalType> {
+    let mut args = args.into_iter();
+    match (args.next(), args.next()) {
+        (Some(first), Some(MalType::List(mut l))) | (Some(first), Some(MalType::Vector(mut l))) => {
+            l.push_front(first);
+            Ok(MalType::List(l))
+        }
+        (Some(first), Some(MalType::Nil)) => Ok(MalType::List(vector![first])),
+        (Some(first), Some(rest @ MalType::LazySeq(_))) => Ok(cons(first, rest)),
+        (Some(first), Some(rest)) => {
+            // Fail now, rather than when the result is realized, if `rest` isn't seqable.
+            rest.first_rest()?;
+            Ok(cons(first, rest))
+        }
+        _ => Err(invalid_args("cons", &args.collect::<Vec<_>>())),
+    }
+}
+
+fn default_first(args: MalList) -> MalResult<MalType> {
+    match args.first() {
+        Some(coll) => Ok(coll.first_rest()?.map_or(MalType::Nil, |(first, _)| first)),
+        None => Err(invalid_args("first", &args)),
+    }
+}
+
+fn default_rest(args: MalList) -> MalResult<MalType> {
+    match args.first() {
+        Some(coll) => {
+            Ok(coll.first_rest()?.map_or(MalType::List(MalSeq::new()), |(_, rest)| rest))
+        }
+        None => Err(invalid_args("rest", &args)),
+    }
+}
+
+fn take(n: i32, coll: MalType) -> MalType {
+    lazy(move || {
+        if n <= 0 {
+            return Ok(MalType::Nil);
+        }
+        Ok(match coll.first_rest()? {
+            Some((first, rest)) => cons(first, take(n - 1, rest)),
+            None => MalType::Nil,
+        })
+    })
+}
+
+fn default_take(args: MalList) -> MalResult<MalType> {
+    match (args.first(), args.get(1)) {
+        (Some(MalType::Int(n)), Some(coll)) => Ok(take(*n, coll.clone())),
+        _ => Err(invalid_args("take", &args)),
+    }
+}
+
+fn range(start: i32, end: Option<i32>, step: i32) -> MalType {
+    lazy(move || {
+        let done = match end {
+            Some(end) => if step < 0 { start <= end } else { start >= end },
+            None => false,
+        };
+        if done {
+            return Ok(MalType::Nil);
+        }
+        let rest = match start.checked_add(step) {
+            Some(next) => range(next, end, step),
+            None => MalType::Nil,
+        };
+        Ok(cons(MalType::Int(start), rest))
+    })
+}
+
+fn default_range(args: MalList) -> MalResult<MalType> {
+    let mut ints = Vec::new();
+    for e in &args {
+        match e {
+            MalType::Int(i) => ints.push(*i),
+            _ => return Err(invalid_args("range", &args)),
+        }
+    }
+    match ints[..] {
+        [] => Ok(range(0, None, 1)),
+        [end] => Ok(range(0, Some(end), 1)),
+        [start, end] => Ok(range(start, Some(end), 1)),
+        [start, end, step] if step != 0 => Ok(range(start, Some(end), step)),
+        _ => Err(invalid_args("range", &args)),
+    }
+}
+
+fn iterate(f: MalType, x: MalType) -> MalType {
+    let current = x.clone();
+    cons(x, lazy(move || {
+        let next = f.apply(vec![current])?;
+        Ok(iterate(f, next))
+    }))
+}
+
+fn default_iterate(args: MalList) -> MalResult<MalType> {
+    match (args.first(), args.get(1)) {
+        (Some(f), Some(x)) => Ok(iterate(f.clone(), x.clone())),
+        _ => Err(invalid_args("iterate", &args)),
+    }
+}
+
+fn repeat(x: MalType) -> MalType {
+    lazy(move || Ok(cons(x.clone(), repeat(x))))
+}
+
+fn default_repeat(args: MalList) -> MalResult<MalType> {
+    match (args.first(), args.get(1)) {
+        (Some(x), None) => Ok(repeat(x.clone())),
+        (Some(MalType::Int(n)), Some(x)) => Ok(take(*n, repeat(x.clone()))),
+        _ => Err(invalid_args("repeat", &args)),
+    }
+}
+
+/// Repeats the elements of `coll` forever, starting part way through at `rest`.
+fn cycle(coll: MalType, rest: MalType) -> MalType {
+    lazy(move || {
+        let (first, rest) = match rest.first_rest()? {
+            Some(cell) => cell,
+            None => match coll.first_rest()? {
+                Some(cell) => cell,
+                None => return Ok(MalType::Nil),
+            },
+        };
+        Ok(cons(first, cycle(coll, rest)))
+    })
+}
+
+fn default_cycle(args: MalList) -> MalResult<MalType> {
+    match args.first() {
+        Some(coll) => Ok(cycle(coll.clone(), coll.clone())),
+        None => Err(invalid_args("cycle", &args)),
+    }
+}
+
+/// Splits each of `colls` into its first element and the rest, or returns `None` if any of them
+/// is empty.
+fn first_rests(colls: &[MalType]) -> MalResult<Option<(MalList, Vec<MalType>)>> {
+    let mut firsts = Vec::with_capacity(colls.len());
+    let mut rests = Vec::with_capacity(colls.len());
+    for coll in colls {
+        match coll.first_rest()? {
+            Some((first, rest)) => {
+                firsts.push(first);
+                rests.push(rest);
+            }
+            None => return Ok(None),
+        }
+    }
+    Ok(Some((firsts, rests)))
+}
+
+fn map(f: MalType, colls: Vec<MalType>) -> MalType {
+    lazy(move || {
+        Ok(match first_rests(&colls)? {
+            Some((firsts, rests)) => cons(f.apply(firsts)?, map(f, rests)),
+            None => MalType::Nil,
+        })
+    })
+}
+
+fn default_map(args: MalList) -> MalResult<MalType> {
+    if args.len() < 2 {
+        return Err(invalid_args("map", &args));
+    }
+    let mut args = args.into_iter();
+    let f = args.next().unwrap();
+    Ok(map(f, args.collect()))
+}
+
+/// The elements of `coll` followed by the elements of each seqable in `colls`.
+fn concat(coll: MalType, colls: MalType) -> MalType {
+    lazy(move || {
+        // Skip empty collections in a loop so long runs of them don't nest.
+        let (mut coll, mut colls) = (coll, colls);
+        loop {
+            if let Some((first, rest)) = coll.first_rest()? {
+                return Ok(cons(first, concat(rest, colls)));
+            }
+            match colls.first_rest()? {
+                Some((next, rest)) => {
+                    coll = next;
+                    colls = rest;
+                }
+                None => return Ok(MalType::Nil),
+            }
+        }
+    })
+}
+
+fn default_mapcat(args: MalList) -> MalResult<MalType> {
+    let mapped = default_map(args.clone()).map_err(|_| invalid_args("mapcat", &args))?;
+    Ok(concat(MalType::Nil, mapped))
+}
+
+/// The elements of `coll` for which `pred` is truthy, or falsy if `keep` is false.
+fn filter(pred: MalType, coll: MalType, keep: bool) -> MalType {
+    lazy(move || {
+        // Skip rejected elements in a loop so long runs of them don't nest.
+        let mut coll = coll;
+        while let Some((first, rest)) = coll.first_rest()? {
+            if pred.apply(vec![first.clone()])?.is_truthy() == keep {
+                return Ok(cons(first, filter(pred, rest, keep)));
+            }
+            coll = rest;
+        }
+        Ok(MalType::Nil)
+    })
+}
+
+fn default_filter(args: MalList) -> MalResult<MalType> {
+    match (args.first(), args.get(1)) {
+        (Some(pred), Some(coll)) => Ok(filter(pred.clone(), coll.clone(), true)),
+        _ => Err(invalid_args("filter", &args)),
+    }
+}
+
+fn default_remove(args: MalList) -> MalResult<MalType> {
+    match (args.first(), args.get(1)) {
+        (Some(pred), Some(coll)) => Ok(filter(pred.clone(), coll.clone(), false)),
+        _ => Err(invalid_args("remove", &args)),
+    }
+}
+
+fn default_reduce(args: MalList) -> MalResult<MalType> {
+    let (f, init, coll) = match &args[..] {
+        [f, coll] => match coll.first_rest()? {
+            Some((first, rest)) => (f, first, rest),
+            None => return f.apply(Vec::new()),
+        },
+        [f, init, coll] => (f, init.clone(), coll.clone()),
+        _ => return Err(invalid_args("reduce", &args)),
+    };
+    coll.seq_iter().try_fold(init, |acc, e| f.apply(vec![acc, e?]))
+}
+
+fn drop(n: i32, coll: MalType) -> MalType {
+    lazy(move || {
+        let mut coll = coll;
+        for _ in 0..n {
+            match coll.first_rest()? {
+                Some((_, rest)) => coll = rest,
+                None => return Ok(MalType::Nil),
+            }
+        }
+        Ok(coll)
+    })
+}
+
+fn default_drop(args: MalList) -> MalResult<MalType> {
+    match (args.first(), args.get(1)) {
+        (Some(MalType::Int(n)), Some(coll)) => Ok(drop(*n, coll.clone())),
+        _ => Err(invalid_args("drop", &args)),
+    }
+}
+
+fn take_while(pred: MalType, coll: MalType) -> MalType {
+    lazy(move || {
+        Ok(match coll.first_rest()? {
+            Some((first, rest)) if pred.apply(vec![first.clone()])?.is_truthy() => {
+                cons(first, take_while(pred, rest))
+            }
+            _ => MalType::Nil,
+        })
+    })
+}
+
+fn default_take_while(args: MalList) -> MalResult<MalType> {
+    match (args.first(), args.get(1)) {
+        (Some(pred), Some(coll)) => Ok(take_while(pred.clone(), coll.clone())),
+        _ => Err(invalid_args("take-while", &args)),
+    }
+}
+
+fn drop_while(pred: MalType, coll: MalType) -> MalType {
+    lazy(move || {
+        let mut coll = coll;
+        while let Some((first, rest)) = coll.first_rest()? {
+            if !pred.apply(vec![first])?.is_truthy() {
+                break;
+            }
+            coll = rest;
+        }
+        Ok(coll)
+    })
+}
+
+fn default_drop_while(args: MalList) -> MalResult<MalType> {
+    match (args.first(), args.get(1)) {
+        (Some(pred), Some(coll)) => Ok(drop_while(pred.clone(), coll.clone())),
+        _ => Err(invalid_args("drop-while", &args)),
+    }
+}
+
+/// Lists of `n` elements of `coll`, starting `step` elements apart. A final short list is
+/// filled from `pad` if there is one, and left out otherwise.
+fn partition(n: usize, step: usize, pad: Option<MalType>, coll: MalType) -> MalType {
+    lazy(move || {
+        let mut part = MalSeq::new();
+        let mut rest = coll.clone();
+        while part.len() < n {
+            match rest.first_rest()? {
+                Some((first, next)) => {
+                    part.push_back(first);
+                    rest = next;
+                }
+                None => break,
+            }
+        }
+        if part.is_empty() {
+            return Ok(MalType::Nil);
+        }
+        if part.len() < n {
+            return Ok(match pad {
+                Some(pad) => {
+                    let fill = pad.seq_iter().take(n - part.len());
+                    part.extend(fill.collect::<MalResult<MalSeq>>()?);
+                    MalType::List(vector![MalType::List(part)])
+                }
+                None => MalType::Nil,
+            });
+        }
+        Ok(cons(MalType::List(part), partition(n, step, pad, drop(step as i32, coll))))
+    })
+}
+
+fn default_partition(args: MalList) -> MalResult<MalType> {
+    let count = |e: &MalType| match e {
+        MalType::Int(i) if *i > 0 => Ok(*i as usize),
+        _ => Err(invalid_args("partition", &args)),
+    };
+    match &args[..] {
+        [n, coll] => Ok(partition(count(n)?, count(n)?, None, coll.clone())),
+        [n, step, coll] => Ok(partition(count(n)?, count(step)?, None, coll.clone())),
+        [n, step, pad, coll] => {
+            Ok(partition(count(n)?, count(step)?, Some(pad.clone()), coll.clone()))
+        }
+        _ => Err(invalid_args("partition", &args)),
+    }
+}
+
+fn interleave(colls: Vec<MalType>) -> MalType {
+    lazy(move || {
+        Ok(match first_rests(&colls)? {
+            Some((firsts, rests)) => {
+                concat(MalType::List(firsts.into()), MalType::List(vector![interleave(rests)]))
+            }
+            None => MalType::Nil,
+        })
+    })
+}
+
+fn default_interleave(args: MalList) -> MalResult<MalType> {
+    Ok(if args.is_empty() { MalType::List(MalSeq::new()) } else { interleave(args) })
+}
+
+fn distinct(coll: MalType, seen: HashSet<MalType>) -> MalType {
+    lazy(move || {
+        let mut coll = coll;
+        while let Some((first, rest)) = coll.first_rest()? {
+            if !seen.contains(&first) {
+                return Ok(cons(first.clone(), distinct(rest, seen.update(first))));
+            }
+            coll = rest;
+        }
+        Ok(MalType::Nil)
+    })
+}
+
+fn default_distinct(args: MalList) -> MalResult<MalType> {
+    match args.first() {
+        Some(coll) => Ok(distinct(coll.clone(), HashSet::new())),
+        None => Err(invalid_args("distinct", &args)),
+    }
+}
+
+fn default_frequencies(args: MalList) -> MalResult<MalType> {
+    let coll = args.first().ok_or_else(|| invalid_args("frequencies", &args))?;
+    let mut acc = MalHashMap { map: HashMap::new() };
+    for e in coll.seq_iter() {
+        let count = acc.map.entry(e?).or_insert(MalType::Int(0));
+        if let MalType::Int(n) = count {
+            *n += 1;
+        }
+    }
+    Ok(MalType::HashMap(acc))
+}
+
+fn default_group_by(args: MalList) -> MalResult<MalType> {
+    let (f, coll) = match &args[..] {
+        [f, coll] => (f, coll),
+        _ => return Err(invalid_args("group-by", &args)),
+    };
+    let mut acc = MalHashMap { map: HashMap::new() };
+    for e in coll.seq_iter() {
+        let e = e?;
+        let group = acc.map.entry(f.apply(vec![e.clone()])?)
+            .or_insert(MalType::Vector(MalSeq::new()));
+        if let MalType::Vector(v) = group {
+            v.push_back(e);
+        }
+    }
+    Ok(MalType::HashMap(acc))
+}
+
+fn default_zipmap(args: MalList) -> MalResult<MalType> {
+    let (keys, vals) = match &args[..] {
+        [keys, vals] => (keys, vals),
+        _ => return Err(invalid_args("zipmap", &args)),
+    };
+    let mut acc = MalHashMap { map: HashMap::new() };
+    for (k, v) in keys.seq_iter().zip(vals.seq_iter()) {
+        acc.map.insert(k?, v?);
+    }
+    Ok(MalType::HashMap(acc))
+}
+
+fn default_some(args: MalList) -> MalResult<MalType> {
+    let (pred, coll) = match &args[..] {
+        [pred, coll] => (pred, coll),
+        _ => return Err(invalid_args("some", &args)),
+    };
+    for e in coll.seq_iter() {
+        let result = pred.apply(vec![e?])?;
+        if result.is_truthy() {
+            return Ok(result);
+        }
+    }
+    Ok(MalType::Nil)
+}
+
+fn default_is_every(args: MalList) -> MalResult<MalType> {
+    let (pred, coll) = match &args[..] {
+        [pred, coll] => (pred, coll),
+        _ => return Err(invalid_args("every?", &args)),
+    };
+    for e in coll.seq_iter() {
+        if !pred.apply(vec![e?])?.is_truthy() {
+            return Ok(MalType::Bool(false));
+        }
+    }
+    Ok(MalType::Bool(true))
+}
+
+fn default_compare(args: MalList) -> MalResult<MalType> {
+    match &args[..] {
+        [a, b] => Ok(MalType::Int(a.cmp(b) as i32)),
+        _ => Err(invalid_args("compare", &args)),
+    }
+}
+
+/// Merge sorts `items` stably with `compare`, stopping at the first error it returns. Unlike
+/// `slice::sort_by`, this doesn't need `compare` to be a total order: a comparator written in
+/// mal may not be, and then the result is some permutation of `items` rather than a panic.
+fn merge_sort<T>(mut items: Vec<T>, compare: &mut impl FnMut(&T, &T) -> MalResult<Ordering>)
+    -> MalResult<Vec<T>> {
+    if items.len() <= 1 {
+        return Ok(items);
+    }
+    let right = items.split_off(items.len() / 2);
+    let mut left = merge_sort(items, compare)?.into_iter().peekable();
+    let mut right = merge_sort(right, compare)?.into_iter().peekable();
+    let mut acc = Vec::with_capacity(left.len() + right.len());
+    while let (Some(l), Some(r)) = (left.peek(), right.peek()) {
+        // Ties take from the left, which keeps equal items in their original order.
+        let next = if compare(r, l)? == Ordering::Less { &mut right } else { &mut left };
+        acc.extend(next.next());
+    }
+    acc.extend(left);
+    acc.extend(right);
+    Ok(acc)
+}
+
+/// Sorts `items` stably by `key`, using `comparator` on the keys if there is one and the natural
+/// order otherwise.
+fn sort_by_key(mut items: Vec<(MalType, MalType)>, comparator: Option<&MalType>)
+    -> MalResult<Vec<(MalType, MalType)>> {
+    match comparator {
+        Some(f) => merge_sort(items, &mut |a, b| compare_with(f, &a.0, &b.0)),
+        None => {
+            items.sort_by(|a, b| a.0.cmp(&b.0));
+            Ok(items)
+        }
+    }
+}
+
+fn sorted(keyfn: Option<&MalType>, comparator: Option<&MalType>, coll: &MalType)
+    -> MalResult<MalType> {
+    let mut items = Vec::new();
+    for e in coll.seq_iter() {
+        let e = e?;
+        let key = match keyfn {
+            Some(f) => f.apply(vec![e.clone()])?,
+            None => e.clone(),
+        };
+        items.push((key, e));
+    }
+    let items = sort_by_key(items, comparator)?;
+    Ok(MalType::List(items.into_iter().map(|(_, e)| e).collect()))
+}
+
+fn default_sort(args: MalList) -> MalResult<MalType> {
+    match &args[..] {
+        [coll] => sorted(None, None, coll),
+        [comparator, coll] => sorted(None, Some(comparator), coll),
+        _ => Err(invalid_args("sort", &args)),
+    }
+}
+
+fn default_sort_by(args: MalList) -> MalResult<MalType> {
+    match &args[..] {
+        [keyfn, coll] => sorted(Some(keyfn), None, coll),
+        [keyfn, comparator, coll] => sorted(Some(keyfn), Some(comparator), coll),
+        _ => Err(invalid_args("sort-by", &args)),
+    }
+}
+
+fn default_reverse(args: MalList) -> MalResult<MalType> {
+    match args.first() {
+        Some(coll) => {
+            let mut acc = MalSeq::new();
+            for e in coll.seq_iter() {
+                acc.push_front(e?);
+            }
+            Ok(MalType::List(acc))
+        }
+        None => Err(invalid_args("reverse", &args)),
+    }
+}
+
+/// The element of `xs` whose key is greatest (or least, if `order` is `Less`). Of several such
+/// elements, the last is returned.
+fn extreme_key(name: &str, args: &[MalType], order: Ordering) -> MalResult<MalType> {
+    let (keyfn, xs) = match args.split_first() {
+        Some((keyfn, xs)) if !xs.is_empty() => (keyfn, xs),
+        _ => return Err(invalid_args(name, args)),
+    };
+    let mut best = xs[0].clone();
+    let mut best_key = keyfn.apply(vec![best.clone()])?;
+    for x in &xs[1..] {
+        let key = keyfn.apply(vec![x.clone()])?;
+        if key.cmp(&best_key) != order.reverse() {
+            best = x.clone();
+            best_key = key;
+        }
+    }
+    Ok(best)
+}
+
+fn default_max_key(args: MalList) -> MalResult<MalType> {
+    extreme_key("max-key", &args, Ordering::Greater)
+}
+
+fn default_min_key(args: MalList) -> MalResult<MalType> {
+    extreme_key("min-key", &args, Ordering::Less)
+}
+
+/// Converts a count of chars into `s` to a byte offset, if it is in range.
+fn byte_offset(s: &str, chars: i32) -> Option<usize> {
+    let chars = usize::try_from(chars).ok()?;
+    s.char_indices().map(|(i, _)| i).chain(std::iter::once(s.len())).nth(chars)
+}
+
+/// A string or character argument, used as text to search for.
+fn text_arg(arg: &MalType) -> Option<String> {
+    match arg {
+        MalType::Str(s) => Some(s.to_string()),
+        MalType::Char(c) => Some(c.to_string()),
+        _ => None,
+    }
+}
+
+fn str_result(s: impl Into<Rc<str>>) -> MalResult<MalType> {
+    Ok(MalType::Str(s.into()))
+}
+
+fn default_subs(args: MalList) -> MalResult<MalType> {
+    let (s, start, end) = match &args[..] {
+        [MalType::Str(s), MalType::Int(start)] => (s, *start, None),
+        [MalType::Str(s), MalType::Int(start), MalType::Int(end)] => (s, *start, Some(*end)),
+        _ => return Err(invalid_args("subs", &args)),
+    };
+    let start = byte_offset(s, start);
+    let end = match end {
+        Some(end) => byte_offset(s, end),
+        None => Some(s.len()),
+    };
+    match (start, end) {
+        (Some(start), Some(end)) if start <= end => str_result(&s[start..end]),
+        _ => Err(MalError::RuntimeError(
+                format!("(subs) index out of range: {}", args_str(&args)))),
+    }
+}
+
+fn default_split(args: MalList) -> MalResult<MalType> {
+    let (s, sep, limit) = match &args[..] {
+        [MalType::Str(s), sep] => (s, sep, None),
+        [MalType::Str(s), sep, MalType::Int(limit)] if *limit > 0 => {
+            (s, sep, Some(*limit as usize))
+        }
+        _ => return Err(invalid_args("split", &args)),
+    };
+    let mut parts: Vec<&str> = match (sep, limit) {
+        (MalType::Regex(re), Some(limit)) => re.regex.splitn(s, limit).collect(),
+        (MalType::Regex(re), None) => re.regex.split(s).collect(),
+        (sep, limit) => {
+            let sep = text_arg(sep).ok_or_else(|| invalid_args("split", &args))?;
+            match limit {
+                Some(limit) => s.splitn(limit, sep.as_str()).collect(),
+                None => s.split(sep.as_str()).collect(),
+            }
+        }
+    };
+    if limit.is_none() {
+        // As in Clojure, trailing empty strings are left out unless there is a limit.
+        while parts.len() > 1 && parts.last() == Some(&"") {
+            parts.pop();
+        }
+    }
+    Ok(MalType::Vector(parts.into_iter().map(|p| MalType::Str(p.into())).collect()))
+}
+
+fn default_join(args: MalList) -> MalResult<MalType> {
+    let (sep, coll) = match &args[..] {
+        [coll] => (String::new(), coll),
+        [sep, coll] => (text_arg(sep).ok_or_else(|| invalid_args("join", &args))?, coll),
+        _ => return Err(invalid_args("join", &args)),
+    };
+    let items = coll.seq_iter().collect::<MalResult<Vec<_>>>()?;
+    str_result(join_args(&items, false, &sep))
+}
+
+/// Applies `f` to a single string argument.
+fn map_string(name: &str, args: &[MalType], f: fn(&str) -> String) -> MalResult<MalType> {
+    match args {
+        [MalType::Str(s)] => str_result(f(s)),
+        _ => Err(invalid_args(name, args)),
+    }
+}
+
+fn default_trim(args: MalList) -> MalResult<MalType> {
+    map_string("trim", &args, |s| s.trim().to_string())
+}
+
+fn default_upper_case(args: MalList) -> MalResult<MalType> {
+    map_string("upper-case", &args, str::to_uppercase)
+}
+
+fn default_lower_case(args: MalList) -> MalResult<MalType> {
+    map_string("lower-case", &args, str::to_lowercase)
+}
+
+/// Applies `test` to a string and a string or character to look for in it.
+fn test_string(name: &str, args: &[MalType], test: fn(&str, &str) -> bool)
+    -> MalResult<MalType> {
+    match args {
+        [MalType::Str(s), text] => match text_arg(text) {
+            Some(text) => Ok(MalType::Bool(test(s, &text))),
+            None => Err(invalid_args(name, args)),
+        },
+        _ => Err(invalid_args(name, args)),
+    }
+}
+
+fn default_starts_with(args: MalList) -> MalResult<MalType> {
+    test_string("starts-with?", &args, |s, prefix| s.starts_with(prefix))
+}
+
+fn default_ends_with(args: MalList) -> MalResult<MalType> {
+    test_string("ends-with?", &args, |s, suffix| s.ends_with(suffix))
+}
+
+fn default_includes(args: MalList) -> MalResult<MalType> {
+    test_string("includes?", &args, |s, text| s.contains(text))
+}
+
+fn default_index_of(args: MalList) -> MalResult<MalType> {
+    let (s, text, from) = match &args[..] {
+        [MalType::Str(s), text] => (s, text, 0),
+        [MalType::Str(s), text, MalType::Int(from)] => (s, text, (*from).max(0)),
+        _ => return Err(invalid_args("index-of", &args)),
+    };
+    let text = text_arg(text).ok_or_else(|| invalid_args("index-of", &args))?;
+    let start = match byte_offset(s, from) {
+        Some(start) => start,
+        None => return Ok(MalType::Nil),
+    };
+    Ok(match s[start..].find(&text) {
+        Some(i) => MalType::Int(from + s[start..start + i].chars().count() as i32),
+        None => MalType::Nil,
+    })
+}
+
+/// Replaces each match of `re` in `s` with `to`, which is either a string, where `$1` or
+/// `${name}` stand for groups, or a function called with each match as `re-find` returns it.
+fn replace_regex(s: &str, re: &MalRegex, to: &MalType) -> MalResult<MalType> {
+    let mut acc = String::with_capacity(s.len());
+    let mut last = 0;
+    for caps in re.regex.captures_iter(s) {
+        let m = caps.get(0).unwrap();
+        acc.push_str(&s[last..m.start()]);
+        match to {
+            MalType::Str(template) => caps.expand(template, &mut acc),
+            f => acc.push_str(&join_args(&[f.apply(vec![match_result(re, &caps, false)])?],
+                                         false, "")),
+        }
+        last = m.end();
+    }
+    acc.push_str(&s[last..]);
+    str_result(acc)
+}
+
+fn default_replace(args: MalList) -> MalResult<MalType> {
+    match &args[..] {
+        [MalType::Str(s), MalType::Regex(re), to] => replace_regex(s, re, to),
+        [MalType::Str(s), from, to] => match (text_arg(from), text_arg(to)) {
+            (Some(from), Some(to)) => str_result(s.replace(&from, &to)),
+            _ => Err(invalid_args("replace", &args)),
+        },
+        _ => Err(invalid_args("replace", &args)),
+    }
+}
+
+fn default_split_lines(args: MalList) -> MalResult<MalType> {
+    match &args[..] {
+        [MalType::Str(s)] => {
+            Ok(MalType::Vector(s.lines().map(|l| MalType::Str(l.into())).collect()))
+        }
+        _ => Err(invalid_args("split-lines", &args)),
+    }
+}
+
+/// What a successful match returns: the matched text if the regex has no groups (unless
+/// `groups` is set), a map from keywords to text if it has named groups, and otherwise a vector
+/// of the whole match followed by each group. Groups that took no part in the match are nil.
+fn match_result(re: &MalRegex, caps: &Captures, groups: bool) -> MalType {
+    let text = |m: Option<Match>| m.map_or(MalType::Nil, |m| MalType::Str(m.as_str().into()));
+    if re.regex.capture_names().any(|name| name.is_some()) {
+        let map = re.regex.capture_names().enumerate()
+            .filter_map(|(i, name)| {
+                name.map(|name| (MalType::Keyword(name.into()), text(caps.get(i))))
+            })
+            .collect();
+        MalType::HashMap(MalHashMap { map })
+    } else if caps.len() > 1 || groups {
+        MalType::Vector((0..caps.len()).map(|i| text(caps.get(i))).collect())
+    } else {
+        text(caps.get(0))
+    }
+}
+
+fn default_re_pattern(args: MalList) -> MalResult<MalType> {
+    match args.first() {
+        Some(MalType::Regex(re)) => Ok(MalType::Regex(re.clone())),
+        Some(MalType::Str(s)) => MalRegex::new(s).map(MalType::Regex)
+            .map_err(|e| MalError::RuntimeError(format!("(re-pattern) invalid regex: {}", e))),
+        _ => Err(invalid_args("re-pattern", &args)),
+    }
+}
+
+fn regex_args<'a>(name: &str, args: &'a [MalType]) -> MalResult<(&'a MalRegex, &'a str)> {
+    match args {
+        [MalType::Regex(re), MalType::Str(s)] => Ok((re, s)),
+        _ => Err(invalid_args(name, args)),
+    }
+}
+
+fn default_re_find(args: MalList) -> MalResult<MalType> {
+    let (re, s) = regex_args("re-find", &args)?;
+    Ok(re.regex.captures(s).map_or(MalType::Nil, |caps| match_result(re, &caps, false)))
+}
+
+fn default_re_matches(args: MalList) -> MalResult<MalType> {
+    let (re, s) = regex_args("re-matches", &args)?;
+    Ok(re.whole.captures(s).map_or(MalType::Nil, |caps| match_result(re, &caps, false)))
+}
+
+fn default_re_seq(args: MalList) -> MalResult<MalType> {
+    let (re, s) = regex_args("re-seq", &args)?;
+    let matches: MalSeq = re.regex.captures_iter(s)
+        .map(|caps| match_result(re, &caps, false))
+        .collect();
+    Ok(if matches.is_empty() { MalType::Nil } else { MalType::List(matches) })
+}
+
+/// The groups of the first match, as a vector or map even if the regex has no groups.
+fn default_re_groups(args: MalList) -> MalResult<MalType> {
+    let (re, s) = regex_args("re-groups", &args)?;
+    Ok(re.regex.captures(s).map_or(MalType::Nil, |caps| match_result(re, &caps, true)))
+}
+
+/// One `%` directive in a `format` string: `%[flags][width][.precision]conversion`.
+#[derive(Default)]
+struct Directive {
+    left: bool,
+    zero: bool,
+    plus: bool,
+    space: bool,
+    width: usize,
+    precision: Option<usize>,
+}
+
+impl Directive {
+    /// Pads `body` to the width, after any sign or `0x`-style prefix when padding with zeros.
+    fn pad(&self, prefix: &str, body: &str) -> String {
+        let len = prefix.chars().count() + body.chars().count();
+        let fill = self.width.saturating_sub(len);
+        if self.left {
+            format!("{}{}{}", prefix, body, " ".repeat(fill))
+        } else if self.zero {
+            format!("{}{}{}", prefix, "0".repeat(fill), body)
+        } else {
+            format!("{}{}{}", " ".repeat(fill), prefix, body)
+        }
+    }
+
+    fn sign(&self, n: i64) -> &'static str {
+        if n < 0 {
+            "-"
+        } else if self.plus {
+            "+"
+        } else if self.space {
+            " "
+        } else {
+            ""
+        }
+    }
+}
+
+/// Formats `args` according to `fmt`, which supports the directives `%s`, `%d`, `%x`, `%X`,
+/// `%o`, `%c`, `%b` (true or false), `%n` and `%%`, with the flags `-`, `0`, `+` and space, a
+/// width, and a precision, which truncates `%s` to that many characters.
+fn format_args(fmt: &str, args: &[MalType]) -> MalResult<String> {
+    let error = |msg: String| MalError::RuntimeError(format!("(format) {}", msg));
+    let mut out = String::new();
+    let mut args = args.iter();
+    let mut chars = fmt.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            out.push(c);
+            continue;
+        }
+        let mut d = Directive::default();
+        while let Some(&flag) = chars.peek() {
+            match flag {
+                '-' => d.left = true,
+                '0' => d.zero = true,
+                '+' => d.plus = true,
+                ' ' => d.space = true,
+                _ => break,
+            }
+            chars.next();
+        }
+        while let Some(digit) = chars.peek().and_then(|c| c.to_digit(10)) {
+            d.width = d.width * 10 + digit as usize;
+            chars.next();
+        }
+        if chars.peek() == Some(&'.') {
+            chars.next();
+            let mut precision = 0;
+            while let Some(digit) = chars.peek().and_then(|c| c.to_digit(10)) {
+                precision = precision * 10 + digit as usize;
+                chars.next();
+            }
+            d.precision = Some(precision);
+        }
+        let conversion = chars.next().ok_or_else(|| error(String::from("incomplete directive")))?;
+        match conversion {
+            '%' => out.push('%'),
+            'n' => out.push('\n'),
+            _ => {
+                let arg = args.next()
+                    .ok_or_else(|| error(format!("missing argument for %{}", conversion)))?;
+                let formatted = match (conversion, arg) {
+                    ('s', _) => {
+                        let s = join_args(std::slice::from_ref(arg), false, "");
+                        let s: String = match d.precision {
+                            Some(p) => s.chars().take(p).collect(),
+                            None => s,
+                        };
+                        d.pad("", &s)
+                    }
+                    ('d', MalType::Int(n)) => {
+                        let n = i64::from(*n);
+                        d.pad(d.sign(n), &n.abs().to_string())
+                    }
+                    ('x', MalType::Int(n)) => d.pad("", &format!("{:x}", n)),
+                    ('X', MalType::Int(n)) => d.pad("", &format!("{:X}", n)),
+                    ('o', MalType::Int(n)) => d.pad("", &format!("{:o}", n)),
+                    ('c', MalType::Char(c)) => d.pad("", &c.to_string()),
+                    ('b', _) => d.pad("", &arg.is_truthy().to_string()),
+                    _ => return Err(error(format!("%{} can't format {}", conversion, arg))),
+                };
+                out.push_str(&formatted);
+            }
+        }
+    }
+    Ok(out)
+}
+
+fn default_format(args: MalList) -> MalResult<MalType> {
+    match args.split_first() {
+        Some((MalType::Str(fmt), rest)) => str_result(format_args(fmt, rest)?),
+        _ => Err(invalid_args("format", &args)),
+    }
+}
+
+fn default_pprint(args: MalList) -> MalResult<MalType> {
+    let width = match args.get(1) {
+        None => PPRINT_WIDTH,
+        Some(MalType::Int(w)) if *w > 0 => *w as usize,
+        _ => return Err(invalid_args("pprint", &args)),
+    };
+    if let Some(expr) = args.first() {
+        println!("{}", pprint(expr, width));
+        Ok(MalType::Nil)
+    } else {
+        Err(invalid_args("pprint", &args))
+    }
+}
+
+/// The builtins bound in `MalEnv::default`.
+pub fn ns() -> Vec<(&'static str, MalFn)> {
+    vec![
+        ("+", default_add),
+        ("-", default_sub),
+        ("*", default_mul),
+        ("/", default_div),
+        ("inc", default_inc),
+        ("dec", default_dec),
+        ("identity", default_identity),
+        ("symbol", default_symbol),
+        ("<", default_lt),
+        ("<=", default_le),
+        (">", default_gt),
+        (">=", default_ge),
+        ("readline", default_readline),
+        ("read-string", default_read_string),
+        ("slurp", default_slurp),
+        ("pr-str", default_pr_str),
+        ("str", default_str),
+        ("prn", default_prn),
+        ("println", default_println),
+        ("pprint", default_pprint),
+        ("char", default_char),
+        ("int", default_int),
+        ("seq", default_seq),
+        ("list", default_list),
+        ("list?", default_is_list),
+        ("nil?", default_is_nil),
+        ("empty?", default_is_empty),
+        ("concat", default_concat),
+        ("count", default_count),
+        ("nth", default_nth),
+        ("cons", default_cons),
+        ("first", default_first),
+        ("rest", default_rest),
+        ("take", default_take),
+        ("range", default_range),
+        ("iterate", default_iterate),
+        ("repeat", default_repeat),
+        ("cycle", default_cycle),
+        ("map", default_map),
+        ("filter", default_filter),
+        ("remove", default_remove),
+        ("reduce", default_reduce),
+        ("drop", default_drop),
+        ("take-while", default_take_while),
+        ("drop-while", default_drop_while),
+        ("partition", default_partition),
+        ("interleave", default_interleave),
+        ("distinct", default_distinct),
+        ("frequencies", default_frequencies),
+        ("group-by", default_group_by),
+        ("zipmap", default_zipmap),
+        ("mapcat", default_mapcat),
+        ("compare", default_compare),
+        ("sort", default_sort),
+        ("sort-by", default_sort_by),
+        ("reverse", default_reverse),
+        ("max-key", default_max_key),
+        ("min-key", default_min_key),
+        ("some", default_some),
+        ("subs", default_subs),
+        ("split", default_split),
+        ("join", default_join),
+        ("trim", default_trim),
+        ("upper-case", default_upper_case),
+        ("lower-case", default_lower_case),
+        ("starts-with?", default_starts_with),
+        ("ends-with?", default_ends_with),
+        ("includes?", default_includes),
+        ("index-of", default_index_of),
+        ("replace", default_replace),
+        ("split-lines", default_split_lines),
+        ("format", default_format),
+        ("re-pattern", default_re_pattern),
+        ("re-find", default_re_find),
+        ("re-matches", default_re_matches),
+        ("re-seq", default_re_seq),
+        ("re-groups", default_re_groups),
+        ("every?", default_is_every),
+        ("=", default_eq),
+        ("hash-set", default_hash_set),
+        ("set", default_set),
+        ("set?", default_is_set),
+        ("conj", default_conj),
+        ("disj", default_disj),
+        ("contains?", default_contains),
+        ("union", default_union),
+        ("intersection", default_intersection),
+        ("difference", default_difference),
+        ("subset?", default_is_subset),
+        ("get", default_get),
+        ("assoc", default_assoc),
+        ("dissoc", default_dissoc),
+        ("sorted-map", default_sorted_map),
+        ("sorted-map-by", default_sorted_map_by),
+        ("sorted-set", default_sorted_set),
+        ("sorted-set-by", default_sorted_set_by),
+        ("subseq", default_subseq),
+        ("rsubseq", default_rsubseq),
+        ("atom", default_atom),
+        ("atom?", default_is_atom),
+        ("deref", default_deref),
+        ("reset!", default_reset),
+        ("swap!", default_swap),
+    ]
+}
+
+/// The builtins bound in `MalEnv::default` that need that environment.
+pub fn env_ns() -> Vec<(&'static str, MalEnvFn)> {
+    vec![("eval", default_eval)]
+}
@@ -1,99 +1,122 @@
+use std::cell::RefCell;
 use std::collections::HashMap;
+use std::rc::{Rc, Weak};
 
+use super::core;
+use super::eval::eval;
+use super::macros;
+use super::printer::PrintLimits;
+use super::reader::read_str;
 use super::types::*;
 
 #[derive(Debug)]
-pub struct MalEnv<'a> {
-    map: HashMap<String, MalType>,
-    outer: Option<&'a MalEnv<'a>>,
+struct EnvInner {
+    // The bindings made directly in this environment. Symbols are interned, so they hash as
+    // integers.
+    frame: RefCell<HashMap<MalSymbol, MalType>>,
+    outer: Option<MalEnv>,
 }
 
-fn default_add(args: MalList) -> MalResult<MalType> {
-    Ok(MalType::Int(
-        args.into_iter().fold(0, |acc, e| {
-            if let MalType::Int(i) = e { acc + i } else { acc }
-        })
-    ))
-}
+/// A handle to an environment that doesn't keep it alive, so that a value bound in an
+/// environment can refer back to it without making a reference cycle.
+#[derive(Debug, Clone)]
+pub struct WeakEnv(Weak<EnvInner>);
 
-fn default_sub(args: MalList) -> MalResult<MalType> {
-    // So far assumes there are only two arguments, and panics otherwise.
-    if args.len() == 2 {
-        if let Some(MalType::Int(i1)) = args.get(0) {
-            if let Some(MalType::Int(i2)) = args.get(1) {
-                return Ok(MalType::Int(i1 - i2));
-            }
-        }
+impl WeakEnv {
+    pub fn upgrade(&self) -> Option<MalEnv> {
+        self.0.upgrade().map(MalEnv)
     }
-    Err(MalError::RuntimeError(format!("(-) has invalid arguments: {:?}", args)))
 }
 
-fn default_mul(args: MalList) -> MalResult<MalType> {
-    Ok(MalType::Int(
-        args.into_iter().fold(1, |acc, e| {
-            if let MalType::Int(i) = e { acc * i } else { acc }
-        })
-    ))
-}
+/// Definitions written in mal that every default environment starts with.
+const PRELUDE: &str = include_str!("prelude.mal");
 
-fn default_div(args: MalList) -> MalResult<MalType> {
-    // So far assumes there are only two arguments, and panics otherwise.
-    if args.len() == 2 {
-        if let Some(MalType::Int(i1)) = args.get(0) {
-            if let Some(MalType::Int(i2)) = args.get(1) {
-                return Ok(MalType::Int(i1 / i2));
-            }
+/// A chain of frames mapping symbols to values. Environments are reference counted, so cloning
+/// one is cheap and clones share their bindings.
+#[derive(Debug, Clone)]
+pub struct MalEnv(Rc<EnvInner>);
+
+impl Default for MalEnv {
+    fn default() -> MalEnv {
+        let env = MalEnv::new(None);
+        for (name, f) in core::ns() {
+            env.set(name, MalType::Fun(name, f));
+        }
+        for (name, f) in core::env_ns() {
+            env.set(name, MalType::EnvFun(name, f, env.downgrade()));
+        }
+        for (name, f) in macros::ns() {
+            env.set(name, MalType::Macro(name, f));
         }
+        env.set("*print-length*", MalType::Nil);
+        env.set("*print-level*", MalType::Nil);
+        let prelude = read_str(&format!("(do {}\nnil)", PRELUDE)).expect("prelude.mal should read");
+        eval(prelude, &env).expect("prelude.mal should evaluate");
+        env
     }
-    Err(MalError::RuntimeError(format!("(/) has invalid arguments: {:?}", args)))
 }
 
-impl<'a> MalEnv<'a> {
-    pub fn default() -> MalEnv<'a> {
-        let mut env = MalEnv::new(None);
-        env.set(String::from("+"), MalType::Fun(default_add));
-        env.set(String::from("-"), MalType::Fun(default_sub));
-        env.set(String::from("*"), MalType::Fun(default_mul));
-        env.set(String::from("/"), MalType::Fun(default_div));
-        env
+impl MalEnv {
+    pub fn new(outer: Option<&MalEnv>) -> MalEnv {
+        MalEnv(Rc::new(EnvInner {
+            frame: RefCell::new(HashMap::new()),
+            outer: outer.cloned(),
+        }))
     }
 
-    pub fn new(outer: Option<&'a MalEnv>) -> MalEnv<'a> {
-        MalEnv {
-            map: HashMap::new(),
-            outer: {
-                if let Some(e) = outer {
-                    Some(&e)
-                } else {
-                    None
-                }
-            }
+    pub fn downgrade(&self) -> WeakEnv {
+        WeakEnv(Rc::downgrade(&self.0))
+    }
+
+    /// The outermost environment in the chain.
+    pub fn root(&self) -> MalEnv {
+        let mut env = self;
+        while let Some(outer) = &env.0.outer {
+            env = outer;
         }
+        env.clone()
+    }
+
+    pub fn set(&self, key: impl Into<MalSymbol>, val: MalType) {
+        self.0.frame.borrow_mut().insert(key.into(), val);
     }
 
-    pub fn set(&mut self, key: String, val: MalType) {
-        self.map.insert(key, val);
+    pub fn find(&self, key: impl Into<MalSymbol>) -> bool {
+        self.get(key).is_some()
     }
 
-    pub fn find(&self, key: &str) -> bool {
-        if self.map.contains_key(key) {
-            true
-        } else if let Some(env) = self.outer {
-            env.find(key)
-        } else {
-            false
+    /// Returns the names bound in this environment and all of its outer environments.
+    pub fn symbols(&self) -> Vec<String> {
+        let mut acc: Vec<String> = self.0.frame.borrow().keys()
+            .map(|k| k.name().to_string())
+            .collect();
+        if let Some(env) = &self.0.outer {
+            acc.extend(env.symbols());
         }
+        acc
     }
 
-    pub fn get(&self, key: &str) -> Option<MalType> {
-        // Technically this isn't implemented the way the instruction suggested, which may or may
-        // not become important later.
-        if self.map.contains_key(key) {
-            Some(self.map.get(key).unwrap().clone())
-        } else if let Some(env) = self.outer {
-            env.get(key)
-        } else {
-            None
+    /// Reads `*print-length*` and `*print-level*`; anything but a non-negative number means no
+    /// limit.
+    pub fn print_limits(&self) -> PrintLimits {
+        let limit = |key| match self.get(key) {
+            Some(MalType::Int(n)) if n >= 0 => Some(n as usize),
+            _ => None,
+        };
+        PrintLimits {
+            length: limit("*print-length*"),
+            level: limit("*print-level*"),
+        }
+    }
+
+    pub fn get(&self, key: impl Into<MalSymbol>) -> Option<MalType> {
+        let key = key.into();
+        let mut env = self;
+        loop {
+            if let Some(val) = env.0.frame.borrow().get(&key) {
+                return Some(val.clone());
+            }
+            env = env.0.outer.as_ref()?;
         }
     }
 }
@@ -0,0 +1,687 @@
+use std::rc::Rc;
+
+use im_rc::HashMap;
+
+use super::env::MalEnv;
+use super::types::*;
+
+/// Forms the evaluator handles itself. Their names can't be rebound.
+pub const SPECIAL_FORMS: &[&str] = &SPECIAL_FORM_NAMES;
+
+fn not_found(s: &MalSymbol) -> MalError {
+    MalError::RuntimeError(format!("{} not found in environment", s))
+}
+
+fn invalid_form(name: &str, form: &MalSeq) -> MalError {
+    let form = MalType::List(form.clone());
+    MalError::RuntimeError(format!("({}) has invalid syntax: {}", name, form))
+}
+
+fn eval_all(exprs: &MalSeq, env: &MalEnv) -> MalResult<MalSeq> {
+    exprs.iter().map(|e| eval_form(e.clone(), env)).collect()
+}
+
+/// Simplifies an expression
+/// - looks up a symbol in the environment.
+/// - evaluates each element of a list, vector, map or set.
+fn eval_ast(expr: MalType, env: &MalEnv) -> MalResult<MalType> {
+    match expr {
+        MalType::Symbol(s) => env.get(s).ok_or_else(|| not_found(&s)),
+        MalType::List(list) => Ok(MalType::List(eval_all(&list, env)?)),
+        MalType::Vector(list) => Ok(MalType::Vector(eval_all(&list, env)?)),
+        MalType::HashMap(m) => {
+            let mut map = m.map.clone();
+            for (k, v) in m.map.iter() {
+                map.insert(k.clone(), eval_form(v.clone(), env)?);
+            }
+            Ok(MalType::HashMap(MalHashMap { map }))
+        }
+        MalType::Set(s) => {
+            let set = s.set.iter().map(|e| eval_form(e.clone(), env))
+                .collect::<MalResult<_>>()?;
+            Ok(MalType::Set(MalSet { set }))
+        }
+        _ => Ok(expr),
+    }
+}
+
+fn update_env(key: &MalType, val: MalType, env: &MalEnv) -> MalResult<()> {
+    if let MalType::Symbol(s) = key {
+        env.set(*s, val);
+        Ok(())
+    } else {
+        Err(MalError::RuntimeError(
+                format!("invalid def: {} = {}", key, val)))
+    }
+}
+
+/// Binds the symbols in the binding form `pattern` to the matching parts of `val` in `env`.
+///
+/// - A symbol is bound to the whole value.
+/// - A vector binds its elements to successive elements of a sequence, `& rest` to the
+///   remainder and `:as name` to the whole. Missing elements are nil.
+/// - A map binds each `name key` entry to the value at `key`. `:keys`, `:strs` and `:syms` list
+///   names to look up as keywords, strings or symbols. `:or` gives defaults for missing names,
+///   evaluated in `env`, and `:as` binds the whole. A sequence of alternating keys and values
+///   (as from `& {:keys [...]}`) is read as a map.
+pub fn destructure(pattern: &MalType, val: MalType, env: &MalEnv) -> MalResult<()> {
+    match pattern {
+        MalType::Symbol(s) => {
+            env.set(*s, val);
+            Ok(())
+        }
+        MalType::Vector(items) => destructure_seq(items, val, env),
+        MalType::HashMap(m) => destructure_map(&m.map, val, env),
+        _ => Err(MalError::RuntimeError(format!("invalid binding form: {}", pattern))),
+    }
+}
+
+fn destructure_seq(items: &MalSeq, val: MalType, env: &MalEnv) -> MalResult<()> {
+    let mut rest = match &val {
+        MalType::Nil => MalType::List(MalSeq::new()),
+        MalType::List(_) | MalType::LazySeq(_) => val.clone(),
+        MalType::Vector(v) => MalType::List(v.clone()),
+        _ => MalType::List(val.seq_iter().collect::<MalResult<_>>()?),
+    };
+    let mut items = items.iter();
+    while let Some(item) = items.next() {
+        let invalid = || MalError::RuntimeError(format!("invalid binding form after {}", item));
+        match item {
+            MalType::Symbol(MalSymbol::AMP) => {
+                destructure(items.next().ok_or_else(invalid)?, rest.clone(), env)?;
+            }
+            MalType::Keyword(k) if &**k == "as" => {
+                destructure(items.next().ok_or_else(invalid)?, val.clone(), env)?;
+            }
+            _ => {
+                let (first, next) = rest.first_rest()?
+                    .unwrap_or((MalType::Nil, MalType::List(MalSeq::new())));
+                destructure(item, first, env)?;
+                rest = next;
+            }
+        }
+    }
+    Ok(())
+}
+
+fn destructure_map(pattern: &HashMap<MalType, MalType>, val: MalType, env: &MalEnv)
+    -> MalResult<()> {
+    let map: HashMap<MalType, MalType> = match &val {
+        MalType::HashMap(m) => m.map.clone(),
+        MalType::SortedMap(m) => m.map.iter().map(|(k, v)| (k.key.clone(), v.clone())).collect(),
+        MalType::Nil => HashMap::new(),
+        MalType::List(_) | MalType::Vector(_) | MalType::LazySeq(_) => {
+            let items = val.seq_iter().collect::<MalResult<Vec<_>>>()?;
+            items.chunks(2).map(|kv| (kv[0].clone(), kv.get(1).cloned().unwrap_or(MalType::Nil)))
+                .collect()
+        }
+        _ => return Err(MalError::RuntimeError(format!("can't destructure {} as a map", val))),
+    };
+    let defaults = match pattern.get(&MalType::Keyword("or".into())) {
+        Some(MalType::HashMap(m)) => m.map.clone(),
+        Some(other) => return Err(MalError::RuntimeError(format!("invalid :or {}", other))),
+        None => HashMap::new(),
+    };
+    let bind = |name: &MalType, key: MalType| match (map.get(&key), defaults.get(name)) {
+        (Some(v), _) => destructure(name, v.clone(), env),
+        (None, Some(default)) => destructure(name, eval_form(default.clone(), env)?, env),
+        (None, None) => destructure(name, MalType::Nil, env),
+    };
+    for (k, v) in pattern.iter() {
+        match (k, v) {
+            (MalType::Keyword(k), MalType::Vector(names))
+                if matches!(&**k, "keys" | "strs" | "syms") => {
+                for name in names.iter() {
+                    let s = match name {
+                        MalType::Symbol(s) => s.name(),
+                        _ => return Err(MalError::RuntimeError(
+                                format!("invalid name in :{}: {}", k, name))),
+                    };
+                    let key = match &**k {
+                        "keys" => MalType::Keyword(s),
+                        "strs" => MalType::Str(s),
+                        _ => MalType::Symbol(MalSymbol::new(&s)),
+                    };
+                    bind(name, key)?;
+                }
+            }
+            (MalType::Keyword(k), _) if &**k == "or" => (),
+            (MalType::Keyword(k), _) if &**k == "as" => destructure(v, val.clone(), env)?,
+            _ => bind(k, v.clone())?,
+        }
+    }
+    Ok(())
+}
+
+/// Gives an anonymous closure the name it is being defined as, for error messages.
+fn name_closure(val: MalType, key: &MalType) -> MalType {
+    match (val, key) {
+        (MalType::Closure(c), MalType::Symbol(s)) if c.name.is_none() => {
+            MalType::Closure(Rc::new(MalClosure { name: Some(*s), ..(*c).clone() }))
+        }
+        (val, _) => val,
+    }
+}
+
+/// Reads the parameters of one body, splitting off a `& rest` binding.
+fn parse_arity(params: &MalType, body: MalSeq) -> Option<MalArity> {
+    let mut params = match params {
+        MalType::List(p) | MalType::Vector(p) => p.clone(),
+        _ => return None,
+    };
+    let amp = params.iter().position(|p| *p == MalType::Symbol(MalSymbol::AMP));
+    let rest = match amp {
+        Some(i) if i + 2 == params.len() => {
+            let rest = params.split_off(i);
+            rest.last().cloned()
+        }
+        Some(_) => return None,
+        None => None,
+    };
+    Some(MalArity { params, rest, body })
+}
+
+/// Reads the name and bodies of `(fn* name? [params] body...)` or
+/// `(fn* name? ([params] body...) ...)`.
+fn parse_fn(list: &MalSeq) -> MalResult<(Option<MalSymbol>, Vec<MalArity>)> {
+    let mut forms = list.skip(1);
+    let name = match forms.front() {
+        Some(MalType::Symbol(s)) => Some(*s),
+        _ => None,
+    };
+    if name.is_some() {
+        forms.pop_front();
+    }
+    let is_arity = |f: &MalType| match f {
+        MalType::List(l) => matches!(l.front(), Some(MalType::Vector(_))),
+        _ => false,
+    };
+    let arities = if !forms.is_empty() && forms.iter().all(is_arity) {
+        forms.iter().map(|f| match f {
+            MalType::List(l) => parse_arity(&l[0], l.skip(1)),
+            _ => None,
+        }).collect::<Option<Vec<_>>>()
+    } else {
+        forms.front().and_then(|params| parse_arity(params, forms.skip(1))).map(|a| vec![a])
+    };
+    Ok((name, arities.ok_or_else(|| invalid_form("fn*", list))?))
+}
+
+/// Builds a closure from a `fn*` form. A named closure can call itself by that name.
+fn make_closure(list: &MalSeq, env: &MalEnv) -> MalResult<MalType> {
+    let (name, arities) = parse_fn(list)?;
+    Ok(MalType::Closure(Rc::new(MalClosure {
+        name,
+        binds_name: name.is_some(),
+        arities,
+        env: env.clone(),
+        is_macro: false,
+    })))
+}
+
+/// What `check_recur` knows about the code around a form: the environment it'll be evaluated in,
+/// and the symbols bound by enclosing `fn*`, `let*` and `loop` forms, which shadow that
+/// environment's macros.
+#[derive(Clone)]
+struct Scope<'a> {
+    env: &'a MalEnv,
+    locals: im_rc::HashSet<MalSymbol>,
+}
+
+impl Scope<'_> {
+    /// Adds each symbol in the binding form `pattern` to the locals.
+    fn bind(&mut self, pattern: &MalType) {
+        match pattern {
+            MalType::Symbol(s) if *s != MalSymbol::AMP => {
+                self.locals.insert(*s);
+            }
+            MalType::List(items) | MalType::Vector(items) => {
+                items.iter().for_each(|p| self.bind(p))
+            }
+            MalType::HashMap(m) => m.map.iter().for_each(|(k, v)| {
+                self.bind(k);
+                self.bind(v);
+            }),
+            _ => {}
+        }
+    }
+
+    fn macro_for(&self, s: MalSymbol) -> Option<MalType> {
+        if self.locals.contains(&s) {
+            None
+        } else {
+            self.env.get(s)
+        }
+    }
+}
+
+/// Checks that each `recur` in `body` is in tail position and passes `arity` arguments, one for
+/// each binding of the enclosing `loop` or function body. `arity` is `None` where there is no
+/// enclosing `loop` or `fn*` to recur to.
+///
+/// This runs once on each form given to `eval`, before any of it is evaluated, so that a
+/// misplaced `recur` is reported even if it is never reached. Calls to builtin macros are
+/// expanded to check the code they produce, unless a local binding shadows the macro. Macros
+/// defined in mal aren't called, as they may have side effects; a `recur` they produce is
+/// rejected only when it is evaluated. So is a call that a builtin macro fails to expand, which
+/// reports its error if it's reached.
+fn check_body(body: &MalSeq, arity: Option<usize>, scope: &Scope) -> MalResult<()> {
+    for (i, form) in body.iter().enumerate() {
+        check_recur(form, i + 1 == body.len(), arity, scope)?;
+    }
+    Ok(())
+}
+
+fn check_recur(form: &MalType, tail: bool, arity: Option<usize>, scope: &Scope)
+    -> MalResult<()> {
+    let list = match form {
+        MalType::List(list) => list,
+        MalType::Vector(items) => return check_body_not_tail(items.iter(), arity, scope),
+        MalType::HashMap(m) => return check_body_not_tail(m.map.values(), arity, scope),
+        MalType::Set(s) => return check_body_not_tail(s.set.iter(), arity, scope),
+        _ => return Ok(()),
+    };
+    let args = list.skip(1);
+    let head = match list.front() {
+        Some(MalType::Symbol(s)) => s,
+        _ => return check_body_not_tail(list.iter(), arity, scope),
+    };
+    match *head {
+        MalSymbol::RECUR => {
+            let expected = match arity {
+                Some(n) if tail => n,
+                Some(_) => return Err(MalError::RuntimeError(
+                        format!("(recur) is not in tail position: {}", form))),
+                None => return Err(MalError::RuntimeError(
+                        format!("(recur) is outside of a loop or fn*: {}", form))),
+            };
+            if args.len() != expected {
+                return Err(MalError::RuntimeError(format!(
+                    "(recur) passes {} arguments, expects {}: {}", args.len(), expected, form)));
+            }
+            check_body_not_tail(args.iter(), arity, scope)
+        }
+        MalSymbol::IF => {
+            check_body_not_tail(args.iter().take(1), arity, scope)?;
+            args.iter().skip(1).try_for_each(|e| check_recur(e, tail, arity, scope))
+        }
+        MalSymbol::DO => check_tail_body(&args, tail, arity, scope),
+        MalSymbol::LET | MalSymbol::LOOP => {
+            let mut inner = scope.clone();
+            if let Some(MalType::List(b)) | Some(MalType::Vector(b)) = args.front() {
+                for pair in b.iter().collect::<Vec<_>>().chunks(2) {
+                    if let Some(value) = pair.get(1) {
+                        check_recur(value, false, arity, &inner)?;
+                    }
+                    inner.bind(pair[0]);
+                }
+                if *head == MalSymbol::LOOP {
+                    return check_body(&args.skip(1), Some(b.len() / 2), &inner);
+                }
+            }
+            check_tail_body(&args.skip(1), tail, arity, &inner)
+        }
+        MalSymbol::FN => {
+            let (name, arities) = parse_fn(list)?;
+            for a in arities {
+                let mut inner = scope.clone();
+                if let Some(name) = name {
+                    inner.locals.insert(name);
+                }
+                a.params.iter().chain(a.rest.iter()).for_each(|p| inner.bind(p));
+                check_body(&a.body, Some(a.recur_args()), &inner)?;
+            }
+            Ok(())
+        }
+        MalSymbol::QUOTE => Ok(()),
+        MalSymbol::QUASIQUOTE => check_unquoted(&args, arity, scope),
+        MalSymbol::TRY => {
+            // The expression is evaluated by a nested call, so it has no tail position.
+            let (expr, catch) = parse_try(list)?;
+            check_recur(&expr, false, arity, scope)?;
+            if let Some((binding, body)) = catch {
+                let mut inner = scope.clone();
+                inner.bind(&binding);
+                check_tail_body(&body, tail, arity, &inner)?;
+            }
+            Ok(())
+        }
+        MalSymbol::LAZY_SEQ => check_body_not_tail(args.iter(), None, scope),
+        MalSymbol::DEF | MalSymbol::DEFMACRO => {
+            check_body_not_tail(args.iter(), arity, scope)
+        }
+        _ => match scope.macro_for(*head) {
+            Some(MalType::Macro(_, expand)) => match expand(args.into_iter().collect()) {
+                Ok(expansion) => check_recur(&expansion, tail, arity, scope),
+                Err(_) => Ok(()),
+            },
+            Some(MalType::Closure(c)) if c.is_macro => Ok(()),
+            _ => check_body_not_tail(list.iter(), arity, scope),
+        },
+    }
+}
+
+/// Checks a body whose last form is in tail position only if the body itself is.
+fn check_tail_body(body: &MalSeq, tail: bool, arity: Option<usize>, scope: &Scope)
+    -> MalResult<()> {
+    for (i, form) in body.iter().enumerate() {
+        check_recur(form, tail && i + 1 == body.len(), arity, scope)?;
+    }
+    Ok(())
+}
+
+fn check_body_not_tail<'a>(mut forms: impl Iterator<Item = &'a MalType>, arity: Option<usize>,
+                           scope: &Scope) -> MalResult<()> {
+    forms.try_for_each(|e| check_recur(e, false, arity, scope))
+}
+
+/// Checks the forms that `quasiquote` evaluates: the arguments of each `unquote` and
+/// `splice-unquote` in `forms`.
+fn check_unquoted(forms: &MalSeq, arity: Option<usize>, scope: &Scope) -> MalResult<()> {
+    for form in forms {
+        match form {
+            MalType::List(l) if matches!(l.front(),
+                Some(MalType::Symbol(MalSymbol::UNQUOTE | MalSymbol::SPLICE_UNQUOTE))) => {
+                check_body_not_tail(l.iter().skip(1), arity, scope)?
+            }
+            MalType::List(l) | MalType::Vector(l) => check_unquoted(l, arity, scope)?,
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+/// Checks `expr` with `check_recur` before it's evaluated in `env`.
+fn check_form(expr: &MalType, env: &MalEnv) -> MalResult<()> {
+    check_recur(expr, false, None, &Scope { env, locals: im_rc::HashSet::new() })
+}
+
+/// Binds `args` to the parameters of `arity` in a new environment enclosed by `env`, for a
+/// `recur` to the body. As in Clojure, a `& rest` parameter takes a single sequence argument.
+fn rebind(arity: &MalArity, env: &MalEnv, args: MalSeq) -> MalResult<MalEnv> {
+    if args.len() != arity.recur_args() {
+        return Err(MalError::RuntimeError(format!(
+            "(recur) passes {} arguments, expects {}", args.len(), arity.recur_args())));
+    }
+    let inner = MalEnv::new(Some(env));
+    let mut args = args.into_iter();
+    for (param, arg) in arity.params.iter().zip(&mut args) {
+        destructure(param, arg, &inner)?;
+    }
+    if let (Some(rest), Some(arg)) = (&arity.rest, args.next()) {
+        destructure(rest, arg, &inner)?;
+    }
+    Ok(inner)
+}
+
+/// Calls a closure. Its body is evaluated with the chosen arity as the target of `recur`.
+pub(crate) fn apply_closure(closure: &Rc<MalClosure>, args: MalList) -> MalResult<MalType> {
+    let scope = closure.scope();
+    let (inner, arity) = closure.bind(&scope, args)?;
+    let last = eval_body(&arity.body, &inner)?;
+    eval_loop(last, inner, Some((arity.clone(), scope)))
+}
+
+/// The argument of `list` if it is `(name x)`.
+fn unquoted(list: &MalSeq, name: MalSymbol) -> Option<&MalType> {
+    match (list.front(), list.get(1)) {
+        (Some(MalType::Symbol(s)), Some(x)) if *s == name && list.len() == 2 => Some(x),
+        _ => None,
+    }
+}
+
+/// The value of `(quasiquote form)`: `form` unevaluated, except that each `(unquote x)` in it is
+/// replaced by the value of `x`, and each `(splice-unquote xs)` in a list or vector by the
+/// elements of the value of `xs`.
+fn quasiquote(form: &MalType, env: &MalEnv) -> MalResult<MalType> {
+    match form {
+        MalType::List(l) => match unquoted(l, MalSymbol::UNQUOTE) {
+            Some(x) => eval_form(x.clone(), env),
+            None => Ok(MalType::List(quasiquote_all(l, env)?)),
+        },
+        MalType::Vector(v) => Ok(MalType::Vector(quasiquote_all(v, env)?)),
+        _ => Ok(form.clone()),
+    }
+}
+
+fn quasiquote_all(items: &MalSeq, env: &MalEnv) -> MalResult<MalSeq> {
+    let mut acc = MalSeq::new();
+    for item in items {
+        let spliced = match item {
+            MalType::List(l) => unquoted(l, MalSymbol::SPLICE_UNQUOTE),
+            _ => None,
+        };
+        match spliced {
+            Some(xs) => {
+                for x in eval_form(xs.clone(), env)?.seq_iter() {
+                    acc.push_back(x?);
+                }
+            }
+            None => acc.push_back(quasiquote(item, env)?),
+        }
+    }
+    Ok(acc)
+}
+
+/// Splits `(try* expr (catch* binding body...))` into the expression and the catch clause, which
+/// is optional.
+fn parse_try(list: &MalSeq) -> MalResult<(MalType, Option<(MalType, MalSeq)>)> {
+    match (list.get(1), list.get(2)) {
+        (Some(expr), None) => Ok((expr.clone(), None)),
+        (Some(expr), Some(MalType::List(c)))
+            if list.len() == 3 && c.len() >= 2
+                && c.front() == Some(&MalType::Symbol(MalSymbol::CATCH)) => {
+            Ok((expr.clone(), Some((c[1].clone(), c.skip(2)))))
+        }
+        _ => Err(invalid_form("try*", list)),
+    }
+}
+
+/// The value a `catch*` clause binds for an error: its message.
+fn error_value(err: MalError) -> MalType {
+    match err {
+        MalError::Empty => MalType::Nil,
+        MalError::NotFoundError => MalType::Str("not found".into()),
+        MalError::Incomplete(msg) | MalError::ParseError(msg) | MalError::RuntimeError(msg) => {
+            MalType::Str(msg.into())
+        }
+    }
+}
+
+/// Evaluates all but the last of `body`, returning the last (or nil) unevaluated so the caller
+/// can evaluate it in tail position.
+fn eval_body(body: &MalSeq, env: &MalEnv) -> MalResult<MalType> {
+    let mut body = body.clone();
+    let last = body.pop_back().unwrap_or(MalType::Nil);
+    for e in body {
+        eval_form(e, env)?;
+    }
+    Ok(last)
+}
+
+/// Resolves an expression to a final value, after checking where it uses `recur`.
+///
+/// Forms in tail position (the branches of `if`, the last form of `do`, `let*`, `loop`, `catch*`
+/// and a closure's body, macro expansions and `recur`) are evaluated by looping rather than
+/// recursing.
+pub fn eval(expr: MalType, env: &MalEnv) -> MalResult<MalType> {
+    check_form(&expr, env)?;
+    eval_form(expr, env)
+}
+
+/// Evaluates a form that has already been checked, or that is part of one.
+fn eval_form(expr: MalType, env: &MalEnv) -> MalResult<MalType> {
+    eval_loop(expr, env.clone(), None)
+}
+
+/// Evaluates `expr`, with `target` as the body a `recur` in tail position goes back to, and the
+/// environment its bindings are made in.
+fn eval_loop(expr: MalType, env: MalEnv, target: Option<(MalArity, MalEnv)>)
+    -> MalResult<MalType> {
+    let mut expr = expr;
+    let mut env = env;
+    let mut target = target;
+    loop {
+        let list = match expr {
+            MalType::List(list) if !list.is_empty() => list,
+            _ => return eval_ast(expr, &env),
+        };
+        let head = list.front().unwrap();
+        if let MalType::Symbol(s) = head {
+            match *s {
+                MalSymbol::DEF => {
+                    let (key, val) = match (list.get(1), list.get(2)) {
+                        (Some(key), Some(val)) if list.len() == 3 => (key, val),
+                        _ => return Err(invalid_form("def!", &list)),
+                    };
+                    let val = name_closure(eval_form(val.clone(), &env)?, key);
+                    update_env(key, val.clone(), &env)?;
+                    return Ok(val);
+                }
+                MalSymbol::DEFMACRO => {
+                    let (key, val) = match (list.get(1), list.get(2)) {
+                        (Some(key), Some(val)) if list.len() == 3 => (key, val),
+                        _ => return Err(invalid_form("defmacro!", &list)),
+                    };
+                    let val = match name_closure(eval_form(val.clone(), &env)?, key) {
+                        MalType::Closure(c) => {
+                            MalType::Closure(Rc::new(MalClosure { is_macro: true, ..(*c).clone() }))
+                        }
+                        _ => return Err(invalid_form("defmacro!", &list)),
+                    };
+                    update_env(key, val.clone(), &env)?;
+                    return Ok(val);
+                }
+                MalSymbol::LET => {
+                    let bindings = match list.get(1) {
+                        Some(MalType::List(b)) | Some(MalType::Vector(b))
+                            if b.len().is_multiple_of(2) => b.clone(),
+                        _ => return Err(invalid_form("let*", &list)),
+                    };
+                    let inner = MalEnv::new(Some(&env));
+                    for i in 0..(bindings.len() / 2) {
+                        let val = eval_form(bindings[i * 2 + 1].clone(), &inner)?;
+                        destructure(&bindings[i * 2], val, &inner)?;
+                    }
+                    expr = eval_body(&list.skip(2), &inner)?;
+                    env = inner;
+                    continue;
+                }
+                MalSymbol::DO => {
+                    expr = eval_body(&list.skip(1), &env)?;
+                    continue;
+                }
+                MalSymbol::IF => {
+                    let (cond, then) = match (list.get(1), list.get(2)) {
+                        (Some(cond), Some(then)) if list.len() <= 4 => (cond, then),
+                        _ => return Err(invalid_form("if", &list)),
+                    };
+                    expr = if eval_form(cond.clone(), &env)?.is_truthy() {
+                        then.clone()
+                    } else {
+                        list.get(3).cloned().unwrap_or(MalType::Nil)
+                    };
+                    continue;
+                }
+                MalSymbol::LOOP => {
+                    let bindings = match list.get(1) {
+                        Some(MalType::Vector(b)) if b.len().is_multiple_of(2) => b.clone(),
+                        _ => return Err(invalid_form("loop", &list)),
+                    };
+                    let body = list.skip(2);
+                    let params: MalSeq = bindings.iter().step_by(2).cloned().collect();
+                    let inner = MalEnv::new(Some(&env));
+                    for i in 0..(bindings.len() / 2) {
+                        let val = eval_form(bindings[i * 2 + 1].clone(), &inner)?;
+                        destructure(&bindings[i * 2], val, &inner)?;
+                    }
+                    expr = eval_body(&body, &inner)?;
+                    target = Some((MalArity { params, rest: None, body }, env));
+                    env = inner;
+                    continue;
+                }
+                MalSymbol::RECUR => {
+                    let (arity, outer) = match &target {
+                        Some(target) => target,
+                        None => return Err(MalError::RuntimeError(format!(
+                            "(recur) is not in tail position of a loop or fn*: {}",
+                            MalType::List(list)))),
+                    };
+                    let inner = rebind(arity, outer, eval_all(&list.skip(1), &env)?)?;
+                    expr = eval_body(&arity.body, &inner)?;
+                    env = inner;
+                    continue;
+                }
+                MalSymbol::FN => return make_closure(&list, &env),
+                MalSymbol::QUOTE => {
+                    return match list.get(1) {
+                        Some(form) if list.len() == 2 => Ok(form.clone()),
+                        _ => Err(invalid_form("quote", &list)),
+                    };
+                }
+                MalSymbol::QUASIQUOTE => {
+                    return match list.get(1) {
+                        Some(form) if list.len() == 2 => quasiquote(form, &env),
+                        _ => Err(invalid_form("quasiquote", &list)),
+                    };
+                }
+                MalSymbol::TRY => {
+                    // If the expression fails, the catch* body is evaluated in tail position with
+                    // the error bound.
+                    let (body, catch) = parse_try(&list)?;
+                    let err = match eval_form(body, &env) {
+                        Ok(val) => return Ok(val),
+                        Err(err) => err,
+                    };
+                    let (binding, body) = match catch {
+                        Some(catch) => catch,
+                        None => return Err(err),
+                    };
+                    let inner = MalEnv::new(Some(&env));
+                    destructure(&binding, error_value(err), &inner)?;
+                    expr = eval_body(&body, &inner)?;
+                    env = inner;
+                    continue;
+                }
+                MalSymbol::LAZY_SEQ => {
+                    // The body is evaluated, in this environment, when the seq is realized.
+                    let body = list.skip(1);
+                    let env = env.clone();
+                    return Ok(MalType::LazySeq(MalLazySeq::new(move || {
+                        let mut result = MalType::Nil;
+                        for e in body {
+                            result = eval_form(e, &env)?;
+                        }
+                        Ok(result)
+                    })));
+                }
+                _ => (),
+            }
+        }
+
+        let op = eval_form(head.clone(), &env)?;
+        match &op {
+            MalType::Macro(_, expand) => {
+                expr = expand(list.skip(1).into_iter().collect())?;
+                continue;
+            }
+            MalType::Closure(c) if c.is_macro => {
+                expr = c.apply(list.skip(1).into_iter().collect())?;
+                continue;
+            }
+            _ => (),
+        }
+        let args = eval_all(&list.skip(1), &env)?;
+        return match op {
+            MalType::Closure(c) => {
+                let scope = c.scope();
+                let (inner, arity) = c.bind(&scope, args.into_iter().collect())?;
+                expr = eval_body(&arity.body, &inner)?;
+                target = Some((arity.clone(), scope));
+                env = inner;
+                continue;
+            }
+            _ => op.apply(args.into_iter().collect()),
+        };
+    }
+}
@@ -3,11 +3,549 @@ pub mod types;
 pub mod reader;
 pub mod printer;
 pub mod env;
+pub mod core;
+pub mod eval;
+pub mod macros;
+pub mod readline;
 
 #[cfg(test)]
 mod tests {
+    use std::rc::Rc;
+
+    use super::env::MalEnv;
+    use super::eval::eval;
+    use super::printer::{pprint, pr_str, set_limits, PrintLimits};
+    use super::reader::{read_str, token_spans};
+    use super::types::*;
+
+    /// Reads and evaluates `s` in `env`, and prints the result readably.
+    fn rep(env: &MalEnv, s: &str) -> String {
+        pr_str(&eval(read_str(s).unwrap(), env).unwrap(), true)
+    }
+
+    /// Reads and evaluates `s` in `env`, which must fail, and returns the error.
+    fn err(env: &MalEnv, s: &str) -> String {
+        format!("{:?}", eval(read_str(s).unwrap(), env).unwrap_err())
+    }
+
+    /// Calls the function bound to `name` in `env`.
+    fn call(env: &MalEnv, name: &str, args: MalList) -> MalResult<MalType> {
+        builtin(env, name).apply(args)
+    }
+
+    /// The value bound to `name` in `env`.
+    fn builtin(env: &MalEnv, name: &str) -> MalType {
+        env.get(name).unwrap()
+    }
+
     #[test]
     fn it_works() {
         assert_eq!(2 + 2, 4);
     }
+
+    #[test]
+    fn read_incomplete_input() {
+        assert!(matches!(read_str("(+ 1"), Err(MalError::Incomplete(_))));
+        assert!(matches!(read_str("[1 (2 3)"), Err(MalError::Incomplete(_))));
+        assert!(matches!(read_str("\"abc"), Err(MalError::Incomplete(_))));
+        assert!(matches!(read_str("(+ 1 ; comment"), Err(MalError::Incomplete(_))));
+        assert!(matches!(read_str("(+ 1]"), Err(MalError::ParseError(_))));
+        assert!(matches!(read_str("  ; comment"), Err(MalError::Empty)));
+    }
+
+    #[test]
+    fn env_symbols_include_outer() {
+        let outer = MalEnv::default();
+        let inner = MalEnv::new(Some(&outer));
+        inner.set(String::from("x"), MalType::Int(1));
+
+        let symbols = inner.symbols();
+        assert!(symbols.contains(&String::from("x")));
+        assert!(symbols.contains(&String::from("+")));
+    }
+
+    #[test]
+    fn token_spans_keep_offsets_and_comments() {
+        assert_eq!(token_spans("(+ 1 \"a b\") ; c"),
+                   vec![(0, "("), (1, "+"), (3, "1"), (5, "\"a b\""), (10, ")"), (12, "; c")]);
+    }
+
+    #[test]
+    fn print_builtin_by_name() {
+        let env = MalEnv::default();
+        assert_eq!(pr_str(&builtin(&env, "+"), true), "#<builtin +>");
+    }
+
+    #[test]
+    fn print_closures_with_params() {
+        let env = MalEnv::default();
+        assert_eq!(rep(&env, "(fn* [x] x)"), "#<fn [x]>");
+        assert_eq!(rep(&env, "(def! f (fn* ([a] a) ([a b & more] b)))"),
+                   "#<fn f [a] [a b & more]>");
+        assert_eq!(rep(&env, "(defmacro! m (fn* [{:keys [x]}] x))"), "#<macro m [{:keys [x]}]>");
+    }
+
+    #[test]
+    fn print_strings_raw_when_not_readable() {
+        let s = MalType::Str("a \"b\"\n".into());
+        assert_eq!(pr_str(&s, true), "\"a \\\"b\\\"\\n\"");
+        assert_eq!(pr_str(&s, false), "a \"b\"\n");
+    }
+
+    #[test]
+    fn display_matches_pr_str() {
+        let expr = read_str("(1 [\"a\" :b] nil)").unwrap();
+        assert_eq!(format!("{}", expr), pr_str(&expr, true));
+        assert_eq!(format!("{}", expr), "(1 [\"a\" :b] nil)");
+    }
+
+    #[test]
+    fn maps_are_keyed_by_any_value() {
+        let env = MalEnv::default();
+        let m = read_str(r#"{:a 1 "a" 2 [1 2] 3}"#).unwrap();
+        let get = |k: &str| call(&env, "get", vec![m.clone(), read_str(k).unwrap()]).unwrap();
+
+        // Keywords and strings with the same name are different keys.
+        assert_eq!(get(":a"), MalType::Int(1));
+        assert_eq!(get(r#""a""#), MalType::Int(2));
+        // A list key finds the equal vector key.
+        assert_eq!(get("(1 2)"), MalType::Int(3));
+        assert_eq!(pr_str(&read_str(r#"{"a" 1}"#).unwrap(), true), r#"{"a" 1}"#);
+
+        let sorted =
+            call(&env, "sorted-map", vec![read_str(":a").unwrap(), MalType::Int(1)]).unwrap();
+        assert_eq!(sorted, read_str("{:a 1}").unwrap());
+        assert_ne!(sorted, read_str(r#"{"a" 1}"#).unwrap());
+    }
+
+    #[test]
+    fn pprint_breaks_only_groups_that_do_not_fit() {
+        let expr = read_str("(1 [2 3] (4 5 6 7 8))").unwrap();
+        assert_eq!(pprint(&expr, 80), "(1 [2 3] (4 5 6 7 8))");
+        assert_eq!(pprint(&expr, 14), "(1\n [2 3]\n (4 5 6 7 8))");
+        assert_eq!(pprint(&expr, 8), "(1\n [2 3]\n (4\n  5\n  6\n  7\n  8))");
+    }
+
+    #[test]
+    fn print_cyclic_atom() {
+        let atom = MalAtom::new(MalType::Nil);
+        *atom.0.borrow_mut() = MalType::Vector(vec![MalType::Atom(atom.clone())].into());
+        let expr = MalType::Atom(atom);
+        assert_eq!(pr_str(&expr, true), "(atom [#<cycle>])");
+        assert_eq!(pprint(&expr, 80), "(atom [#<cycle>])");
+
+        // Keys of a map go through the same cycle check as values.
+        let atom = MalAtom::new(MalType::Nil);
+        let map = im_rc::hashmap! { MalType::Atom(atom.clone()) => MalType::Int(1) };
+        *atom.0.borrow_mut() = MalType::HashMap(MalHashMap { map });
+        let expr = MalType::Atom(atom);
+        assert_eq!(pr_str(&expr, true), "(atom {#<cycle> 1})");
+        assert_eq!(pprint(&expr, 80), "(atom {#<cycle> 1})");
+    }
+
+    #[test]
+    fn print_length_and_level() {
+        let expr = read_str("(1 [2 [3]] 4 5)").unwrap();
+        set_limits(PrintLimits { length: Some(3), level: Some(2) });
+        assert_eq!(pr_str(&expr, true), "(1 [2 ...] 4 ...)");
+        assert_eq!(pprint(&expr, 80), "(1 [2 ...] 4 ...)");
+        set_limits(PrintLimits::default());
+    }
+
+    #[test]
+    fn read_and_print_chars() {
+        let expr = read_str("[\\a \\newline \\( \\é \\u03bb]").unwrap();
+        assert_eq!(pr_str(&expr, true), "[\\a \\newline \\( \\é \\λ]");
+        assert_eq!(pr_str(&expr, false), "[a \n ( é λ]");
+        assert!(matches!(read_str("\\bogus"), Err(MalError::ParseError(_))));
+    }
+
+    #[test]
+    fn read_sets() {
+        let set = read_str("#{1 [2 3] :a}").unwrap();
+        assert_eq!(set, read_str("#{:a (2 3) 1}").unwrap());
+        assert_ne!(set, read_str("#{1 :a}").unwrap());
+        assert!(matches!(read_str("#{1 2 1}"), Err(MalError::ParseError(_))));
+    }
+
+    #[test]
+    fn sorted_collections_use_comparator() {
+        let env = MalEnv::default();
+        let ints = |is: &[i32]| is.iter().map(|&i| MalType::Int(i)).collect::<MalList>();
+
+        let mut args = vec![builtin(&env, ">")];
+        args.extend(ints(&[1, 3, 2]));
+        let set = call(&env, "sorted-set-by", args).unwrap();
+        assert_eq!(pr_str(&set, true), "#{3 2 1}");
+        assert_eq!(set, call(&env, "sorted-set", ints(&[2, 1, 3])).unwrap());
+
+        let range = call(&env, "subseq", vec![set, builtin(&env, "<"), MalType::Int(2)]).unwrap();
+        assert_eq!(pr_str(&range, true), "(3)");
+
+        let mut args = vec![builtin(&env, "str")];
+        args.extend(ints(&[1, 2, 3, 4]));
+        assert!(call(&env, "sorted-map-by", args).is_err());
+    }
+
+    #[test]
+    fn updates_leave_original_collections_alone() {
+        let env = MalEnv::default();
+
+        let v = read_str("[1 2 3]").unwrap();
+        let w = call(&env, "conj", vec![v.clone(), MalType::Int(4)]).unwrap();
+        assert_eq!(pr_str(&v, true), "[1 2 3]");
+        assert_eq!(pr_str(&w, true), "[1 2 3 4]");
+
+        let a = MalType::Keyword("a".into());
+        let m = read_str("{:a 1}").unwrap();
+        let n = call(&env, "assoc", vec![m.clone(), a.clone(), MalType::Int(2)]).unwrap();
+        assert_eq!(call(&env, "get", vec![m, a.clone()]).unwrap(), MalType::Int(1));
+        assert_eq!(call(&env, "get", vec![n, a]).unwrap(), MalType::Int(2));
+    }
+
+    #[test]
+    fn env_lookup_shares_values() {
+        let env = MalEnv::default();
+        env.set(String::from("s"), MalType::Str("abc".into()));
+        match (env.get("s"), env.get("s")) {
+            (Some(MalType::Str(a)), Some(MalType::Str(b))) => assert!(std::rc::Rc::ptr_eq(&a, &b)),
+            other => panic!("unexpected lookup result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn symbols_are_interned() {
+        assert_eq!(MalSymbol::new("abc"), MalSymbol::from(String::from("abc")));
+        assert_ne!(MalSymbol::new("abc"), MalSymbol::new("abd"));
+        assert_eq!(read_str("abc").unwrap(), MalType::Symbol(MalSymbol::new("abc")));
+
+        // Special forms have fixed ids, for the evaluator to dispatch on.
+        assert_eq!(MalSymbol::new("let*"), MalSymbol::LET);
+        assert_eq!(MalSymbol::new("&"), MalSymbol::AMP);
+        for name in SPECIAL_FORM_NAMES.iter() {
+            assert_eq!(&*MalSymbol::new(name).name(), *name);
+        }
+
+        let outer = MalEnv::default();
+        let inner = MalEnv::new(Some(&outer));
+        inner.set("x", MalType::Int(1));
+        inner.set("x", MalType::Int(3));
+        assert_eq!(inner.get("x"), Some(MalType::Int(3)));
+        assert_eq!(inner.get("+"), outer.get("+"));
+    }
+
+    #[test]
+    fn lazy_seqs_realize_in_constant_stack() {
+        let env = MalEnv::default();
+
+        let mut seq = MalType::List(vec![MalType::Int(1)].into());
+        for _ in 0..100_000 {
+            let inner = seq;
+            seq = MalType::LazySeq(MalLazySeq::new(move || Ok(inner)));
+        }
+        assert_eq!(pr_str(&seq, true), "(1)");
+
+        // Filtering a long run of rejected elements, then dropping the realized range.
+        let range = call(&env, "range", vec![]).unwrap();
+        let taken = call(&env, "take", vec![MalType::Int(100_000), range]);
+        let none = call(&env, "filter", vec![builtin(&env, "set?"), taken.unwrap()]).unwrap();
+        assert_eq!(call(&env, "first", vec![none]).unwrap(), MalType::Nil);
+
+        let nats = call(&env, "range", vec![]).unwrap();
+        let first = call(&env, "take", vec![MalType::Int(3), nats]).unwrap();
+        assert_eq!(first, read_str("[0 1 2]").unwrap());
+    }
+
+    #[test]
+    fn sequence_functions_take_builtins() {
+        let env = MalEnv::default();
+
+        let sums = call(&env, "map", vec![builtin(&env, "+"), read_str("[1 2 3]").unwrap(),
+                                    call(&env, "range", vec![]).unwrap()]).unwrap();
+        assert_eq!(sums, read_str("(1 3 5)").unwrap());
+        assert_eq!(call(&env, "reduce", vec![builtin(&env, "+"), sums]).unwrap(), MalType::Int(9));
+
+        let parts =
+            call(&env, "partition", vec![MalType::Int(2), read_str("[1 2 3 4 5]").unwrap()]);
+        assert_eq!(parts.unwrap(), read_str("((1 2) (3 4))").unwrap());
+
+        let freqs = call(&env, "frequencies", vec![read_str("[:a :b :a]").unwrap()]).unwrap();
+        assert_eq!(freqs, read_str("{:a 2 :b 1}").unwrap());
+    }
+
+    #[test]
+    fn seq_and_conj_take_hash_maps_and_sets() {
+        let env = MalEnv::default();
+
+        assert_eq!(rep(&env, "(seq {:a 1})"), "([:a 1])");
+        assert_eq!(rep(&env, "(seq #{1})"), "(1)");
+        assert_eq!(rep(&env, "(seq {})"), "nil");
+        assert_eq!(rep(&env, "(seq #{})"), "nil");
+        assert_eq!(rep(&env, "(sort (map first {:b 2 :a 1}))"), "(:a :b)");
+        assert_eq!(rep(&env, "(= (conj {:a 1} [:b 2] [:a 3]) {:a 3 :b 2})"), "true");
+        assert!(err(&env, "(conj {:a 1} [:b])").contains("(conj) has invalid arguments"));
+    }
+
+    #[test]
+    fn sort_is_stable_and_propagates_comparator_errors() {
+        let env = MalEnv::default();
+
+        let pairs = read_str("[[2 :a] [1 :b] [2 :c] [1 :d]]").unwrap();
+        let args = vec![builtin(&env, "first"), builtin(&env, ">"), pairs];
+        let sorted = call(&env, "sort-by", args).unwrap();
+        assert_eq!(sorted, read_str("([2 :a] [2 :c] [1 :b] [1 :d])").unwrap());
+
+        let args = vec![builtin(&env, "pr-str"), read_str("[3 1 2]").unwrap()];
+        let result = call(&env, "sort", args);
+        assert!(matches!(result, Err(MalError::RuntimeError(_))));
+
+        // A comparator that isn't a total order gives some permutation rather than a panic.
+        assert_eq!(rep(&env, "(count (sort (fn* [a b] (- (* a 3) (* b 5))) (range 500)))"), "500");
+        assert_eq!(rep(&env, "(sort (fn* [a b] (< (count a) (count b))) [\"bb\" \"a\" \"c\"])"),
+                   "(\"a\" \"c\" \"bb\")");
+    }
+
+    #[test]
+    fn string_functions_count_chars() {
+        let env = MalEnv::default();
+        let s = |s: &str| MalType::Str(s.into());
+
+        assert_eq!(call(&env, "subs", vec![s("héllo"), MalType::Int(1), MalType::Int(3)]).unwrap(),
+                   s("él"));
+        assert_eq!(call(&env, "index-of", vec![s("héllo"), MalType::Char('l')]).unwrap(),
+                   MalType::Int(2));
+        assert_eq!(call(&env, "split", vec![s("a,b,,c,,"), s(",")]).unwrap(),
+                   read_str("[\"a\" \"b\" \"\" \"c\"]").unwrap());
+        assert_eq!(call(&env, "format", vec![s("%-4s|%04d|%x"), s("é"), MalType::Int(-7),
+                                       MalType::Int(255)]).unwrap(),
+                   s("é   |-007|ff"));
+    }
+
+    #[test]
+    fn regexes_read_print_and_match() {
+        let env = MalEnv::default();
+
+        let re = read_str(r#"#"(?P<k>\w+)=(?P<v>\d+)""#).unwrap();
+        assert_eq!(pr_str(&re, true), r#"#"(?P<k>\w+)=(?P<v>\d+)""#);
+
+        let found = call(&env, "re-find", vec![re.clone(), MalType::Str("x a=12".into())]).unwrap();
+        assert_eq!(found, read_str(r#"{:k "a" :v "12"}"#).unwrap());
+        let whole = call(&env, "re-matches", vec![re, MalType::Str("x a=12".into())]).unwrap();
+        assert_eq!(whole, MalType::Nil);
+
+        let parts = call(&env, "split", vec![MalType::Str("a1b22c".into()),
+                                       read_str(r#"#"\d+""#).unwrap()]).unwrap();
+        assert_eq!(parts, read_str(r#"["a" "b" "c"]"#).unwrap());
+    }
+
+    #[test]
+    fn builtin_macros_expand_before_evaluation() {
+        let env = MalEnv::default();
+
+        assert_eq!(rep(&env, "(-> 5 inc (- 2) (* 3))"), "12");
+        assert_eq!(rep(&env, "(->> 5 (- 2))"), "-3");
+        assert_eq!(rep(&env, "(cond-> 1 true inc false (* 10) true (* 2))"), "4");
+        assert_eq!(rep(&env, "(some-> {:a 1} (get :b) inc)"), "nil");
+        assert_eq!(rep(&env, "(as-> [1 2] v (cons 0 v) (rest v))"), "(1 2)");
+
+        // Each argument is evaluated at most once, and evaluation stops at the deciding value.
+        rep(&env, "(def! n (atom 0))");
+        assert_eq!(rep(&env, "(and (swap! n inc) false (swap! n inc))"), "false");
+        assert_eq!(rep(&env, "(or (deref n) (swap! n inc))"), "1");
+        assert_eq!(rep(&env, "(doto n (swap! inc) (swap! inc))"), "(atom 3)");
+        assert!(err(&env, "(inc 2147483647)").contains("(inc) overflowed"));
+        assert!(err(&env, "(dec -2147483648)").contains("(dec) overflowed"));
+        assert!(err(&env, "(+ 1 2147483647)").contains("(+) overflowed"));
+        assert!(err(&env, "(* 65536 65536)").contains("(*) overflowed"));
+        assert_eq!(rep(&env, "(+ 2147483647 -1 1)"), "2147483647");
+
+        // Expansions don't depend on how the caller has bound the names they use.
+        assert_eq!(rep(&env, "(let* [= (fn* [a b] true) nil? =] (some-> 1 inc))"), "2");
+
+        // Macros are ordinary bindings, so lib files can redefine them.
+        rep(&env, "(def! when 3)");
+        assert_eq!(rep(&env, "when"), "3");
+    }
+
+    #[test]
+    fn prelude_is_loaded_into_default_env() {
+        let env = MalEnv::default();
+
+        assert_eq!(rep(&env, "(not nil)"), "true");
+        assert_eq!(rep(&env, "(cond false 1 (= 1 2) 2 :else 3)"), "3");
+        assert_eq!(rep(&env, "(cond false 1)"), "nil");
+
+        // Calls in tail position don't grow the stack.
+        rep(&env, "(def! count-down (fn* (n) (if (= n 0) :done (count-down (- n 1)))))");
+        assert_eq!(rep(&env, "(count-down 100000)"), ":done");
+        assert!(err(&env, "((fn* (a b) a) 1)").contains("called with 1 arguments, expects 2"));
+    }
+
+    #[test]
+    fn quasiquote_and_try() {
+        let env = MalEnv::default();
+
+        assert_eq!(pr_str(&read_str("'(a `(b ~c ~@d) @e)").unwrap(), true),
+                   "(quote (a (quasiquote (b (unquote c) (splice-unquote d))) (deref e)))");
+        rep(&env, "(def! xs [2 3])");
+        assert_eq!(rep(&env, "`(1 ~(first xs) ~@xs [~@xs] ~@nil)"), "(1 2 2 3 [2 3])");
+        assert_eq!(rep(&env, "`(a (b ~'c))"), "(a (b c))");
+
+        assert_eq!(rep(&env, "(try* (undefined) (catch* e e))"),
+                   "\"undefined not found in environment\"");
+        assert_eq!(rep(&env, "(try* 1 (catch* e 2))"), "1");
+        assert!(err(&env, "(try* (undefined))").contains("undefined not found"));
+        assert_eq!(rep(&env,
+                       "(loop [i 0] (if (< i 3) (try* (undefined) (catch* _ (recur (inc i)))) i))"),
+                   "3");
+        assert!(err(&env, "(loop [i 0] (try* (recur 1)))").contains("not in tail position"));
+
+        // lib/load-file-once.mal uses try* to define itself only once.
+        rep(&env, "(load-file \"../../lib/load-file-once.mal\")");
+        assert_eq!(rep(&env, "(load-file-once \"../../lib/trivial.mal\")"), "nil");
+        assert_eq!(rep(&env, "(zero? 0)"), "true");
+        assert_eq!(rep(&env, "(load-file-once \"../../lib/trivial.mal\")"), "nil");
+    }
+
+    #[test]
+    fn eval_is_a_function_over_the_root_env() {
+        let env = MalEnv::default();
+
+        assert_eq!(rep(&env, "(map eval (list 1 (quote (+ 1 2))))"), "(1 3)");
+        assert_eq!(rep(&env, "eval"), "#<builtin eval>");
+        rep(&env, "(def! x :root)");
+        assert_eq!(rep(&env, "(let* [x :local] (eval (quote x)))"), ":root");
+        assert!(err(&env, "(let* [y 1] (eval (quote y)))").contains("y not found"));
+        assert!(err(&env, "(eval (quote (do (recur))))").contains("outside of a loop"));
+    }
+
+    #[test]
+    fn functions_take_rest_params_arities_and_destructuring() {
+        let env = MalEnv::default();
+
+        rep(&env, "(def! f (fn* ([a] [a]) ([a b & more] [a b more])))");
+        assert_eq!(rep(&env, "(f 1)"), "[1]");
+        assert_eq!(rep(&env, "(f 1 2)"), "[1 2 ()]");
+        assert_eq!(rep(&env, "(f 1 2 3 4)"), "[1 2 (3 4)]");
+        assert!(err(&env, "(f)").contains("(f) called with 0 arguments"));
+
+        assert_eq!(rep(&env, "(let* [[a [b c] & more] [1 [2 3] 4 5]] [a b c more])"),
+                   "[1 2 3 (4 5)]");
+        assert_eq!(rep(&env, "(let* [{:keys [x y] :or {x 0} :as m} {:y 2}] [x y m])"),
+                   "[0 2 {:y 2}]");
+        assert_eq!(rep(&env, "((fn* [[a] {b :b}] [a b]) (list 1) {:b 2})"), "[1 2]");
+        assert_eq!(rep(&env, "((fn* [& {:keys [k]}] k) :k 7)"), "7");
+    }
+
+    #[test]
+    fn named_functions_call_themselves_without_a_cycle() {
+        let env = MalEnv::default();
+
+        let fact = read_str("(fn* fact [n] (if (< n 2) 1 (* n (fact (dec n)))))").unwrap();
+        let fact = eval(fact, &env).unwrap();
+        assert_eq!(fact.apply(vec![MalType::Int(5)]).unwrap(), MalType::Int(120));
+        let weak = match &fact {
+            MalType::Closure(c) => Rc::downgrade(c),
+            _ => unreachable!(),
+        };
+        drop(fact);
+        assert!(weak.upgrade().is_none());
+
+        // The name stays bound after a recur.
+        assert_eq!(rep(&env,
+                       "((fn* f [n] (if (> n 0) (recur (dec n)) (if (= n 0) (f -1) :done))) 3)"),
+                   ":done");
+    }
+
+    #[test]
+    fn recur_rebinds_in_tail_position_only() {
+        let env = MalEnv::default();
+
+        assert_eq!(rep(&env, "(loop [i 0 acc []] (if (< i 3) (recur (inc i) (conj acc i)) acc))"),
+                   "[0 1 2]");
+        rep(&env, "(def! down (fn* [n] (cond (> n 0) (recur (dec n)) :else :done)))");
+        assert_eq!(rep(&env, "(down 10000)"), ":done");
+
+        // Misplaced recurs are rejected when the fn* is defined, not when they're reached.
+        assert!(err(&env, "(fn* [n] (if false (+ 1 (recur n)) n))")
+                .contains("not in tail position"));
+        assert!(err(&env, "(loop [a 1 b 2] (recur 1))").contains("passes 1 arguments, expects 2"));
+        assert!(err(&env, "(do (recur))").contains("outside of a loop"));
+        assert!(err(&env, "(fn* [n] (when n (+ 1 (recur n))))").contains("not in tail position"));
+
+        // A local binding shadows a macro of the same name, so the call isn't expanded.
+        assert_eq!(rep(&env, "((fn* [when] (when)) (fn* [] 1))"), "1");
+        assert_eq!(rep(&env,
+                       "(let* [when list] (loop [i 0] (if (< i 2) (recur (inc i)) (when i))))"),
+                   "(2)");
+        assert_eq!(rep(&env, "((fn* [{:keys [when]}] (when)) {:when (fn* [] 2)})"), "2");
+
+        // A macro call that fails to expand is reported when it's reached, not by the check.
+        rep(&env, "(def! bad (fn* [] (when)))");
+        assert!(err(&env, "(bad)").contains("(when) has invalid arguments"));
+
+        // Macros defined in mal aren't expanded by the check, so they run only when evaluated,
+        // and a recur they misplace is rejected when it's reached.
+        rep(&env, "(def! n (atom 0))");
+        rep(&env, "(defmacro! counted (fn* [x] (swap! n inc) x))");
+        rep(&env, "(def! f (fn* [x] (counted x)))");
+        assert_eq!(rep(&env, "(deref n)"), "0");
+        rep(&env, "(f 1)");
+        rep(&env, "(f 2)");
+        assert_eq!(rep(&env, "(loop [i 0] (if (< i 3) (recur (inc i)) (counted i)))"), "3");
+        assert_eq!(rep(&env, "(deref n)"), "3");
+        rep(&env, "(defmacro! misplaced (fn* [] (list (quote +) 1 (list (quote recur)))))");
+        rep(&env, "(def! g (fn* [] (misplaced)))");
+        assert!(err(&env, "(g)").contains("not in tail position"));
+    }
+
+    #[test]
+    fn collections_and_keywords_are_callable() {
+        let env = MalEnv::default();
+
+        assert_eq!(rep(&env, "(:name {:name \"x\"})"), "\"x\"");
+        assert_eq!(rep(&env, "(:age {:name \"x\"} 0)"), "0");
+        assert_eq!(rep(&env, "({:k 1} :k)"), "1");
+        assert_eq!(rep(&env, "(#{1 2} 3)"), "nil");
+        assert_eq!(rep(&env, "([1 2 3] 0)"), "1");
+        assert_eq!(rep(&env, "(map :a [{:a 1} {:a 2}])"), "(1 2)");
+
+        assert!(err(&env, "([1 2 3] 3)").contains("out of bounds"));
+        assert!(err(&env, "(1 2 3)").contains("1 is not a function"));
+        // Arguments in error messages are printed as mal values.
+        assert!(err(&env, "(:k 1 2 3)").contains("(:k) has invalid arguments: (1 2 3)"));
+        assert!(err(&env, "(inc :a)").contains("(inc) has invalid arguments: (:a)"));
+        assert!(err(&env, "(nth [1] 5)").contains("(nth) index out of bounds: ([1] 5)"));
+    }
+
+    #[test]
+    fn swap_calls_any_function() {
+        let env = MalEnv::default();
+
+        rep(&env, "(def! a (atom 1))");
+        assert_eq!(rep(&env, "(swap! a (fn* [x y] (+ x y)) 10)"), "11");
+        rep(&env, "(reset! a {:n 2})");
+        assert_eq!(rep(&env, "(swap! a :n)"), "2");
+        assert_eq!(rep(&env, "(deref a)"), "2");
+    }
+
+    #[test]
+    fn set_operations_accept_sorted_sets() {
+        let env = MalEnv::default();
+
+        assert_eq!(rep(&env, "(= (union #{1} (sorted-set 2 3)) #{1 2 3})"), "true");
+        assert_eq!(rep(&env, "(= (intersection (sorted-set 1 2 3) #{2 3 4}) #{2 3})"), "true");
+        assert_eq!(rep(&env, "(= (difference #{1 2 3} (sorted-set 2)) #{1 3})"), "true");
+        assert_eq!(rep(&env, "(subset? (sorted-set 1) #{1 2})"), "true");
+    }
+
+    #[test]
+    fn comparators_can_be_closures() {
+        let env = MalEnv::default();
+
+        assert_eq!(rep(&env, "(sorted-set-by (fn* [a b] (> a b)) 1 3 2)"), "#{3 2 1}");
+        assert_eq!(rep(&env, "(sorted-map-by (fn* [a b] (compare b a)) 1 :a 2 :b)"), "{2 :b 1 :a}");
+        assert_eq!(rep(&env, "(sort-by :n (fn* [a b] (> a b)) [{:n 1} {:n 2}])"),
+                   "({:n 2} {:n 1})");
+    }
 }
@@ -0,0 +1,201 @@
+use std::cell::Cell;
+
+use im_rc::vector;
+
+use super::core::{default_is_nil, invalid_args};
+use super::types::*;
+
+// Builtin macros. Each one is given its arguments unevaluated and returns the form to evaluate
+// in their place. Temporaries are bound to fresh symbols from `gensym`, so they can't capture
+// the caller's names.
+//
+// `->` and `->>` do what ../lib/threading.mal defines in mal, without loading it. Loading it
+// still works, and replaces them with its own definitions.
+
+thread_local! {
+    static GENSYM_COUNTER: Cell<u32> = const { Cell::new(0) };
+}
+
+/// Returns a fresh symbol of the form `G__n`.
+pub fn gensym() -> MalType {
+    let n = GENSYM_COUNTER.with(|c| {
+        c.set(c.get() + 1);
+        c.get()
+    });
+    MalType::Symbol(MalSymbol::new(&format!("G__{}", n)))
+}
+
+fn sym(name: &str) -> MalType {
+    MalType::Symbol(MalSymbol::new(name))
+}
+
+fn list(items: MalSeq) -> MalType {
+    MalType::List(items)
+}
+
+/// `(let* [name val] body...)`
+fn let_form(bindings: MalSeq, body: MalSeq) -> MalType {
+    let mut form = vector![sym("let*"), MalType::Vector(bindings)];
+    form.append(body);
+    list(form)
+}
+
+/// Threads `x` into `form`: as its first argument if `last` is false, otherwise as its last.
+/// A form that isn't a list is called with `x` alone.
+fn thread(x: MalType, form: MalType, last: bool) -> MalType {
+    match form {
+        MalType::List(mut items) if !items.is_empty() => {
+            if last {
+                items.push_back(x);
+            } else {
+                items.insert(1, x);
+            }
+            list(items)
+        }
+        f => list(vector![f, x]),
+    }
+}
+
+fn thread_all(name: &str, args: MalList, last: bool) -> MalResult<MalType> {
+    let mut args = args.into_iter();
+    let x = args.next().ok_or_else(|| invalid_args(name, &[]))?;
+    Ok(args.fold(x, |x, form| thread(x, form, last)))
+}
+
+fn macro_thread_first(args: MalList) -> MalResult<MalType> {
+    thread_all("->", args, false)
+}
+
+fn macro_thread_last(args: MalList) -> MalResult<MalType> {
+    thread_all("->>", args, true)
+}
+
+fn macro_when(args: MalList) -> MalResult<MalType> {
+    let mut args: MalSeq = args.into();
+    let test = args.pop_front().ok_or_else(|| invalid_args("when", &[]))?;
+    args.push_front(sym("do"));
+    Ok(list(vector![sym("if"), test, list(args)]))
+}
+
+fn macro_when_not(args: MalList) -> MalResult<MalType> {
+    let mut args: MalSeq = args.into();
+    let test = args.pop_front().ok_or_else(|| invalid_args("when-not", &[]))?;
+    args.push_front(sym("do"));
+    Ok(list(vector![sym("if"), test, MalType::Nil, list(args)]))
+}
+
+fn macro_if_not(args: MalList) -> MalResult<MalType> {
+    match args.as_slice() {
+        [test, then] => Ok(list(vector![sym("if"), test.clone(), MalType::Nil, then.clone()])),
+        [test, then, otherwise] => {
+            Ok(list(vector![sym("if"), test.clone(), otherwise.clone(), then.clone()]))
+        }
+        _ => Err(invalid_args("if-not", &args)),
+    }
+}
+
+/// Expands `and` (if `all` is true) or `or` over `args`, binding each value to a temporary so
+/// it is evaluated once.
+fn logic(args: MalList, all: bool) -> MalType {
+    let mut args = args.into_iter().rev();
+    let last = match args.next() {
+        Some(last) => last,
+        None if all => return MalType::Bool(true),
+        None => return MalType::Nil,
+    };
+    args.fold(last, |rest, x| {
+        let g = gensym();
+        let test = if all {
+            list(vector![sym("if"), g.clone(), rest, g.clone()])
+        } else {
+            list(vector![sym("if"), g.clone(), g.clone(), rest])
+        };
+        let_form(vector![g, x], vector![test])
+    })
+}
+
+fn macro_and(args: MalList) -> MalResult<MalType> {
+    Ok(logic(args, true))
+}
+
+fn macro_or(args: MalList) -> MalResult<MalType> {
+    Ok(logic(args, false))
+}
+
+/// Threads `x` through each form, rebinding a temporary at each step to `step(temp, form)`.
+fn thread_steps(name: &str, args: MalList, step: impl Fn(MalType, MalType) -> MalResult<MalType>)
+    -> MalResult<MalType> {
+    let mut args = args.into_iter();
+    let x = args.next().ok_or_else(|| invalid_args(name, &[]))?;
+    let g = gensym();
+    let mut bindings = vector![g.clone(), x];
+    for form in args {
+        bindings.push_back(g.clone());
+        bindings.push_back(step(g.clone(), form)?);
+    }
+    Ok(let_form(bindings, vector![g]))
+}
+
+fn macro_cond_thread(args: MalList) -> MalResult<MalType> {
+    if args.len().is_multiple_of(2) {
+        return Err(invalid_args("cond->", &args));
+    }
+    let mut args = args.into_iter();
+    let x = args.next().unwrap();
+    let g = gensym();
+    let mut bindings = vector![g.clone(), x];
+    while let (Some(test), Some(form)) = (args.next(), args.next()) {
+        let step = thread(g.clone(), form, false);
+        bindings.push_back(g.clone());
+        bindings.push_back(list(vector![sym("if"), test, step, g.clone()]));
+    }
+    Ok(let_form(bindings, vector![g]))
+}
+
+fn macro_some_thread(args: MalList) -> MalResult<MalType> {
+    thread_steps("some->", args, |g, form| {
+        // The test calls the builtin itself, so it works even where `nil?` has been rebound.
+        let is_nil = list(vector![MalType::Fun("nil?", default_is_nil), g.clone()]);
+        Ok(list(vector![sym("if"), is_nil, MalType::Nil, thread(g, form, false)]))
+    })
+}
+
+fn macro_as_thread(args: MalList) -> MalResult<MalType> {
+    match args.get(1) {
+        Some(name @ MalType::Symbol(_)) => {
+            let name = name.clone();
+            let mut bindings = vector![name.clone(), args[0].clone()];
+            for form in args.into_iter().skip(2) {
+                bindings.push_back(name.clone());
+                bindings.push_back(form);
+            }
+            Ok(let_form(bindings, vector![name]))
+        }
+        _ => Err(invalid_args("as->", &args)),
+    }
+}
+
+fn macro_doto(args: MalList) -> MalResult<MalType> {
+    let mut args = args.into_iter();
+    let x = args.next().ok_or_else(|| invalid_args("doto", &[]))?;
+    let g = gensym();
+    let mut body: MalSeq = args.map(|form| thread(g.clone(), form, false)).collect();
+    body.push_back(g.clone());
+    Ok(let_form(vector![g, x], body))
+}
+
+pub fn ns() -> Vec<(&'static str, MalFn)> {
+    vec![
+        ("->", macro_thread_first),
+        ("->>", macro_thread_last),
+        ("when", macro_when),
+        ("when-not", macro_when_not),
+        ("if-not", macro_if_not),
+        ("and", macro_and),
+        ("or", macro_or),
+        ("cond->", macro_cond_thread),
+        ("some->", macro_some_thread),
+        ("as->", macro_as_thread),
+        ("doto", macro_doto),
+    ]
+}
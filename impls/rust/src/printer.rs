@@ -1,51 +1,385 @@
+use std::cell::{Cell, RefCell};
+use std::fmt;
+use std::io;
+use std::rc::Rc;
+
+use super::reader::CHAR_NAMES;
 use super::types::*;
-use regex::Regex;
 
-fn escape_str(s: &str) -> String {
-    let re = Regex::new(r"(\\)").unwrap();
-    let result = re.replace_all(s, r#"\\"#);
+/// Optional limits on how much of a collection is printed, set from `*print-length*` and
+/// `*print-level*`. Elided elements and collections nested too deeply print as `...`.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct PrintLimits {
+    pub length: Option<usize>,
+    pub level: Option<usize>,
+}
 
-    let re = Regex::new(r#"(\n)"#).unwrap();
-    let result = re.replace_all(&result, "\\n");
+thread_local! {
+    static LIMITS: Cell<PrintLimits> =
+        const { Cell::new(PrintLimits { length: None, level: None }) };
+}
 
-    let re = Regex::new(r#"(")"#).unwrap();
-    let result = re.replace_all(&result, "\\\"");
-    result.into_owned()
+/// Sets the limits used by all subsequent printing on this thread.
+pub fn set_limits(limits: PrintLimits) {
+    LIMITS.with(|l| l.set(limits));
 }
 
-pub fn pr_str(expr: MalType, print_readably: bool) -> String {
-    match expr {
-        MalType::Nil => String::from("nil"),
-        MalType::Fun(_) => "TODO: cannot print fn pointers".to_string(),
-        MalType::Bool(b) => b.to_string(),
-        MalType::Int(i) => i.to_string(),
-        MalType::Symbol(s) => s,
-        MalType::Keyword(s) => format!(":{}", s),
-        MalType::Str(s) => {
-            if print_readably {
-                format!("\"{}\"", escape_str(&s))
-            } else {
-                format!("\"{}\"", s)
+fn write_escaped<W: fmt::Write + ?Sized>(out: &mut W, s: &str) -> fmt::Result {
+    // Needs to be kept up to date with the reverse operation in reader.rs
+    for c in s.chars() {
+        match c {
+            '\\' => out.write_str("\\\\")?,
+            '\n' => out.write_str("\\n")?,
+            '"' => out.write_str("\\\"")?,
+            _ => out.write_char(c)?,
+        }
+    }
+    Ok(())
+}
+
+/// State for printing one value: the limits in effect, how deeply nested the current collection
+/// is, and the atoms currently being printed, which is how reference cycles are spotted.
+struct Printer {
+    print_readably: bool,
+    limits: PrintLimits,
+    depth: usize,
+    atoms: Vec<*const RefCell<MalType>>,
+}
+
+impl Printer {
+    fn new(print_readably: bool) -> Printer {
+        Printer {
+            print_readably,
+            limits: LIMITS.with(|l| l.get()),
+            depth: 0,
+            atoms: Vec::new(),
+        }
+    }
+
+    fn too_deep(&self) -> bool {
+        self.limits.level.is_some_and(|level| self.depth >= level)
+    }
+
+    fn too_long(&self, i: usize) -> bool {
+        self.limits.length.is_some_and(|length| i >= length)
+    }
+
+    /// Runs `f` with `atom` marked as being printed, or returns `None` if it already is.
+    fn enter_atom<T>(&mut self, atom: &Rc<RefCell<MalType>>, f: impl FnOnce(&mut Printer) -> T)
+        -> Option<T> {
+        let ptr = Rc::as_ptr(atom);
+        if self.atoms.contains(&ptr) {
+            return None;
+        }
+        self.atoms.push(ptr);
+        let result = f(self);
+        self.atoms.pop();
+        Some(result)
+    }
+
+    /// Realizes as much of a lazy seq as will be printed. Realization stops at an error, which
+    /// callers that care about it should check for before printing.
+    fn lazy_items(&self, seq: &MalType) -> Vec<MalType> {
+        if self.too_deep() {
+            return Vec::new();
+        }
+        let items = seq.seq_iter().map_while(Result::ok);
+        match self.limits.length {
+            Some(length) => items.take(length + 1).collect(),
+            None => items.collect(),
+        }
+    }
+
+    fn write_seq<'e, W: fmt::Write + ?Sized>(&mut self, out: &mut W,
+                                             exprs: impl Iterator<Item = &'e MalType>,
+                                             open: &str, close: char) -> fmt::Result {
+        if self.too_deep() {
+            return out.write_str("...");
+        }
+        self.depth += 1;
+        out.write_str(open)?;
+        for (i, e) in exprs.enumerate() {
+            if i > 0 {
+                out.write_char(' ')?;
+            }
+            if self.too_long(i) {
+                out.write_str("...")?;
+                break;
             }
-        },
-        MalType::List(exprs) => {
-            let acc: Vec<String> = exprs.into_iter()
-                .map(|e| pr_str(e, print_readably)).collect();
-            format!("({})", acc.join(" "))
+            self.write(out, e)?;
         }
-        MalType::Vector(exprs) => {
-            let acc: Vec<String> = exprs.into_iter()
-                .map(|e| pr_str(e, print_readably)).collect();
-            format!("[{}]", acc.join(" "))
+        self.depth -= 1;
+        out.write_char(close)
+    }
+
+    fn write_map<'e, K: 'e, W: fmt::Write + ?Sized>(
+        &mut self, out: &mut W, entries: impl Iterator<Item = (&'e K, &'e MalType)>,
+        write_key: impl Fn(&mut Printer, &mut W, &K) -> fmt::Result) -> fmt::Result {
+        if self.too_deep() {
+            return out.write_str("...");
         }
-        MalType::HashMap(map) => {
-            let mut acc = Vec::new();
+        self.depth += 1;
+        out.write_char('{')?;
+        for (i, (k, v)) in entries.enumerate() {
+            if i > 0 {
+                out.write_char(' ')?;
+            }
+            if self.too_long(i) {
+                out.write_str("...")?;
+                break;
+            }
+            write_key(self, out, k)?;
+            out.write_char(' ')?;
+            self.write(out, v)?;
+        }
+        self.depth -= 1;
+        out.write_char('}')
+    }
 
-            for (k, v) in map.map {
-                acc.push(format!("{} {}", k, pr_str(v, print_readably)));
+    fn write<W: fmt::Write + ?Sized>(&mut self, out: &mut W, expr: &MalType) -> fmt::Result {
+        match expr {
+            MalType::Nil => out.write_str("nil"),
+            MalType::Fun(name, _) | MalType::EnvFun(name, ..) => {
+                write!(out, "#<builtin {}>", name)
+            }
+            MalType::Macro(name, _) => write!(out, "#<macro {}>", name),
+            MalType::Closure(c) => write!(out, "{:?}", c),
+            MalType::Bool(b) => write!(out, "{}", b),
+            MalType::Int(i) => write!(out, "{}", i),
+            MalType::Char(c) => {
+                if self.print_readably {
+                    match CHAR_NAMES.iter().find(|&&(_, named)| named == *c) {
+                        Some((name, _)) => write!(out, "\\{}", name),
+                        None => write!(out, "\\{}", c),
+                    }
+                } else {
+                    out.write_char(*c)
+                }
+            },
+            MalType::Symbol(s) => write!(out, "{}", s),
+            MalType::Keyword(s) => write!(out, ":{}", s),
+            MalType::Str(s) => {
+                if self.print_readably {
+                    out.write_char('"')?;
+                    write_escaped(out, s)?;
+                    out.write_char('"')
+                } else {
+                    out.write_str(s)
+                }
+            },
+            MalType::Regex(r) => {
+                if self.print_readably {
+                    // Only quotes are escaped; backslashes already mean what the reader expects.
+                    write!(out, "#\"{}\"", r.as_str().replace('"', "\\\""))
+                } else {
+                    out.write_str(r.as_str())
+                }
+            },
+            MalType::List(exprs) => self.write_seq(out, exprs.iter(), "(", ')'),
+            MalType::Vector(exprs) => self.write_seq(out, exprs.iter(), "[", ']'),
+            MalType::LazySeq(_) => {
+                let items = self.lazy_items(expr);
+                self.write_seq(out, items.iter(), "(", ')')
+            }
+            MalType::Set(set) => self.write_seq(out, set.set.iter(), "#{", '}'),
+            MalType::HashMap(map) => {
+                self.write_map(out, map.map.iter(), |p, out, k| p.write(out, k))
             }
+            MalType::SortedMap(map) => {
+                self.write_map(out, map.map.iter(), |p, out, k| p.write(out, &k.key))
+            }
+            MalType::SortedSet(set) => {
+                self.write_seq(out, set.set.iter().map(|k| &k.key), "#{", '}')
+            }
+            MalType::Atom(atom) => {
+                self.enter_atom(&atom.0, |p| {
+                    out.write_str("(atom ")?;
+                    p.write(out, &atom.0.borrow())?;
+                    out.write_char(')')
+                }).unwrap_or_else(|| out.write_str("#<cycle>"))
+            }
+        }
+    }
+}
+
+/// Writes `expr` to `out` without building intermediate strings. See `pr_str` for the format.
+pub fn write_expr<W: fmt::Write + ?Sized>(out: &mut W, expr: &MalType, print_readably: bool)
+    -> fmt::Result {
+    Printer::new(print_readably).write(out, expr)
+}
+
+/// Writes `expr` to an `io::Write` such as stdout or a file.
+pub fn write_io<W: io::Write + ?Sized>(out: &mut W, expr: &MalType, print_readably: bool)
+    -> io::Result<()> {
+    write!(out, "{}", printed(expr, print_readably))
+}
 
-            format!("{{{}}}", acc.join(" "))
+/// A value paired with a printing mode, so it can be used with `format!`, `write!` and friends.
+pub struct Printed<'a> {
+    expr: &'a MalType,
+    print_readably: bool,
+}
+
+pub fn printed(expr: &MalType, print_readably: bool) -> Printed<'_> {
+    Printed { expr, print_readably }
+}
+
+impl fmt::Display for Printed<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write_expr(f, self.expr, self.print_readably)
+    }
+}
+
+/// Displays readably, as `pr-str` does.
+impl fmt::Display for MalType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write_expr(f, self, true)
+    }
+}
+
+/// Prints `expr` as a string.
+///
+/// With `print_readably`, strings are quoted and escaped so the output can be read back (as by
+/// `pr-str` and `prn`). Without it, strings print as their raw contents (as by `str` and
+/// `println`).
+///
+/// Characters print as `\c` or `\newline` readably, and as themselves otherwise. Regexes print
+/// as `#"pattern"` readably, and as their pattern otherwise.
+///
+/// Builtin functions print as `#<builtin name>` and builtin macros as `#<macro name>`. Those
+/// defined in mal print as `#<fn name [params]>` or `#<macro name [params]>`, with one params
+/// vector per arity. An atom that contains itself prints as `#<cycle>` where it recurs. These
+/// forms are for display only and cannot be read back by the reader.
+pub fn pr_str(expr: &MalType, print_readably: bool) -> String {
+    let mut acc = String::new();
+    write_expr(&mut acc, expr, print_readably).expect("writing to a String cannot fail");
+    acc
+}
+
+/// Layout tree for `pprint`. A group is printed on one line if it fits in the remaining width;
+/// otherwise each of its own `Line`s becomes a newline indented to `indent` columns past the
+/// group's start, and nested groups get to decide for themselves.
+enum Doc {
+    Text(String),
+    Line,
+    Group { indent: usize, width: usize, items: Vec<Doc> },
+}
+
+impl Doc {
+    fn width(&self) -> usize {
+        match self {
+            Doc::Text(s) => s.chars().count(),
+            Doc::Line => 1,
+            Doc::Group { width, .. } => *width,
+        }
+    }
+
+    fn text(s: &str) -> Doc {
+        Doc::Text(s.to_string())
+    }
+
+    fn group(indent: usize, items: Vec<Doc>) -> Doc {
+        let width = items.iter().map(Doc::width).sum();
+        Doc::Group { indent, width, items }
+    }
+
+    fn render(&self, out: &mut String, col: &mut usize, base: usize, flat: bool, width: usize) {
+        match self {
+            Doc::Text(s) => {
+                out.push_str(s);
+                *col += s.chars().count();
+            }
+            Doc::Line => {
+                if flat {
+                    out.push(' ');
+                    *col += 1;
+                } else {
+                    out.push('\n');
+                    out.extend(std::iter::repeat_n(' ', base));
+                    *col = base;
+                }
+            }
+            Doc::Group { indent, width: group_width, items } => {
+                let start = *col;
+                let flat = flat || start + group_width <= width;
+                for item in items {
+                    item.render(out, col, start + indent, flat, width);
+                }
+            }
         }
     }
 }
+
+impl Printer {
+    /// Lays out a collection for `pprint`, calling `item` to lay out each element.
+    fn seq_doc<T>(&mut self, elements: impl Iterator<Item = T>, open: &str, close: &str,
+                  mut item: impl FnMut(&mut Printer, T) -> Doc) -> Doc {
+        if self.too_deep() {
+            return Doc::text("...");
+        }
+        self.depth += 1;
+        let mut items = vec![Doc::text(open)];
+        for (i, e) in elements.enumerate() {
+            if i > 0 {
+                items.push(Doc::Line);
+            }
+            if self.too_long(i) {
+                items.push(Doc::text("..."));
+                break;
+            }
+            items.push(item(self, e));
+        }
+        items.push(Doc::text(close));
+        self.depth -= 1;
+        Doc::group(open.len(), items)
+    }
+
+    fn doc(&mut self, expr: &MalType) -> Doc {
+        match expr {
+            MalType::List(exprs) => self.seq_doc(exprs.iter(), "(", ")", |p, e| p.doc(e)),
+            MalType::Vector(exprs) => self.seq_doc(exprs.iter(), "[", "]", |p, e| p.doc(e)),
+            MalType::LazySeq(_) => {
+                let items = self.lazy_items(expr);
+                self.seq_doc(items.iter(), "(", ")", |p, e| p.doc(e))
+            }
+            MalType::Set(set) => self.seq_doc(set.set.iter(), "#{", "}", |p, e| p.doc(e)),
+            MalType::HashMap(map) => {
+                self.seq_doc(map.map.iter(), "{", "}", |p, (k, v)| {
+                    // Keep each value on the same line as its key.
+                    let key = p.doc(k);
+                    Doc::group(0, vec![key, Doc::text(" "), p.doc(v)])
+                })
+            }
+            MalType::SortedMap(map) => {
+                self.seq_doc(map.map.iter(), "{", "}", |p, (k, v)| {
+                    let key = p.doc(&k.key);
+                    Doc::group(0, vec![key, Doc::text(" "), p.doc(v)])
+                })
+            }
+            MalType::SortedSet(set) => {
+                self.seq_doc(set.set.iter(), "#{", "}", |p, k| p.doc(&k.key))
+            }
+            MalType::Atom(atom) => {
+                self.enter_atom(&atom.0, |p| {
+                    let inner = p.doc(&atom.0.borrow());
+                    Doc::group(1, vec![Doc::text("(atom"), Doc::Line, inner, Doc::text(")")])
+                }).unwrap_or_else(|| Doc::text("#<cycle>"))
+            }
+            _ => {
+                let mut acc = String::new();
+                self.write(&mut acc, expr).expect("writing to a String cannot fail");
+                Doc::Text(acc)
+            }
+        }
+    }
+}
+
+/// Pretty prints `expr` readably, breaking lists, vectors and maps across lines so that the
+/// output fits in `width` columns where possible. Elements of a broken collection are aligned
+/// one column past its opening delimiter.
+pub fn pprint(expr: &MalType, width: usize) -> String {
+    let mut out = String::new();
+    let mut col = 0;
+    Printer::new(true).doc(expr).render(&mut out, &mut col, 0, false, width);
+    out
+}
@@ -1,8 +1,28 @@
-use std::collections::HashMap;
+use std::cell::RefCell;
+use std::collections::BTreeSet;
+use im_rc::{vector, HashMap, HashSet};
 use regex::Regex;
 
 use super::types::*;
 
+// The following regular expression (PCRE) will match all mal tokens. Character literals (a
+// backslash and at least one more character) are matched before plain symbols so that `\(`
+// and `\"` stay whole.
+// [\s,]*(~@|#\{|[\[\]{}()'`~^@]|#?"(?:\\.|[^\\"])*"?|;.*|\\\S[^\s\[\]{}('"`,;)]*|[^\s\[\]{}('"`,;)]*)
+const TOKENS_RE_STR: &str = r#"[\s,]*(~@|#\{|[\[\]{}()'`~^@]|#?"(?:\\.|[^\\"])*"?|;.*|\\\S[^\s\[\]{}('"`,;)]*|[^\s\[\]{}('"`,;)]*)"#;
+
+thread_local! {
+    // Every keyword read so far, for REPL tab completion.
+    static KEYWORDS: RefCell<BTreeSet<String>> = const { RefCell::new(BTreeSet::new()) };
+    // Compiling the token regex costs far more than reading a typical form, so it's done once.
+    static TOKENS_RE: Regex = Regex::new(TOKENS_RE_STR).unwrap();
+}
+
+/// Returns the names (without the leading ':') of all keywords the reader has seen.
+pub fn seen_keywords() -> Vec<String> {
+    KEYWORDS.with(|k| k.borrow().iter().cloned().collect())
+}
+
 struct TokenState<'a> {
     tokens: Vec<&'a str>,
     idx: usize,
@@ -11,7 +31,7 @@ struct TokenState<'a> {
 impl<'a> TokenState<'a> {
     pub fn next(&mut self) -> MalResult<&'a str> {
         if self.idx >= self.tokens.len() {
-            return Err(MalError::ParseError("unexpected EOF".to_string()))
+            return Err(MalError::Incomplete("unexpected EOF".to_string()))
         }
 
         self.idx += 1;
@@ -20,56 +40,105 @@ impl<'a> TokenState<'a> {
 
     pub fn peek(&self) -> MalResult<&'a str> {
         if self.idx >= self.tokens.len() {
-            return Err(MalError::ParseError("unexpected EOF".to_string()))
+            return Err(MalError::Incomplete("unexpected EOF".to_string()))
         }
 
         Ok(self.tokens[self.idx])
     }
 }
 
+/// Reads a single form from `s`.
+///
+/// Returns `MalError::Empty` if `s` contains only whitespace and comments, and
+/// `MalError::Incomplete` if the input ends before the form is closed (so an interactive REPL can
+/// keep reading more lines).
 pub fn read_str(s: &str) -> MalResult<MalType> {
     let mut tokens = tokenize(s);
+    if tokens.tokens.is_empty() {
+        return Err(MalError::Empty);
+    }
     read_form(&mut tokens)
 }
 
-fn tokenize(s: &str) -> TokenState {
-    // The following regular expression (PCRE) will match all mal tokens.
-    // [\s,]*(~@|[\[\]{}()'`~^@]|"(?:\\.|[^\\"])*"?|;.*|[^\s\[\]{}('"`,;)]*)
-    let regex_str = r#"[\s,]*(~@|[\[\]{}()'`~^@]|"(?:\\.|[^\\"])*"?|;.*|[^\s\[\]{}('"`,;)]*)"#;
-    let tokens_re = Regex::new(regex_str).unwrap();
-
+/// Splits `s` into mal tokens, including comments, paired with the byte offset at which each
+/// token starts. Used by the reader and by the REPL's syntax highlighter.
+pub fn token_spans(s: &str) -> Vec<(usize, &str)> {
     let mut acc = Vec::new();
-    for caps in tokens_re.captures_iter(s) {
-        acc.push(caps.get(1).unwrap().as_str().trim())
-    }
+    TOKENS_RE.with(|tokens_re| {
+        for caps in tokens_re.captures_iter(s) {
+            let m = caps.get(1).unwrap();
+            let tok = m.as_str().trim_end();
+            // The last group matches the empty string at the end of input.
+            if !tok.is_empty() {
+                acc.push((m.start(), tok))
+            }
+        }
+    });
+    acc
+}
+
+fn tokenize(s: &str) -> TokenState<'_> {
+    // Comments are dropped here so that they can also appear inside lists.
+    let tokens = token_spans(s).into_iter()
+        .map(|(_, tok)| tok)
+        .filter(|tok| !tok.starts_with(';'))
+        .collect();
     TokenState {
-        tokens: acc,
+        tokens,
         idx: 0
     }
 }
 
 fn read_form(tokens: &mut TokenState) -> MalResult<MalType> {
     match tokens.next()? {
-        "" => {
-            Err(MalError::Empty)
-        },
         "(" => {
-            read_list(tokens, ")")
+            Ok(MalType::List(read_list(tokens, ")")?))
         },
         "[" => {
-            read_list(tokens, "]")
+            Ok(MalType::Vector(read_list(tokens, "]")?))
         },
         "{" => {
-            read_list(tokens, "}")
+            let items = read_list(tokens, "}")?;
+            let mut m = HashMap::new();
+            for i in 0..items.len()/2 {
+                let k = items.get(i*2).unwrap();
+                let v = items.get(i*2+1).unwrap();
+                m.insert(k.clone(), v.clone());
+            }
+
+            Ok(MalType::HashMap(MalHashMap {
+                map: m
+            }))
+        },
+        "#{" => {
+            let mut set = HashSet::new();
+            for item in read_list(tokens, "}")? {
+                let msg = format!("duplicate set element: {}", item);
+                if set.insert(item).is_some() {
+                    return Err(MalError::ParseError(msg));
+                }
+            }
+            Ok(MalType::Set(MalSet { set }))
         },
+        "'" => read_wrapped("quote", tokens),
+        "`" => read_wrapped("quasiquote", tokens),
+        "~" => read_wrapped("unquote", tokens),
+        "~@" => read_wrapped("splice-unquote", tokens),
+        "@" => read_wrapped("deref", tokens),
         tok => {
             read_atom(tok)
         },
     }
 }
 
-fn read_list(tokens: &mut TokenState, end: &'static str) -> MalResult<MalType> {
-    let mut items = Vec::new();
+/// Reads the form after a reader macro such as `'`, and wraps it as `(name form)`.
+fn read_wrapped(name: &str, tokens: &mut TokenState) -> MalResult<MalType> {
+    Ok(MalType::List(vector![MalType::Symbol(name.into()), read_form(tokens)?]))
+}
+
+/// Reads forms up to and including the closing delimiter `end`.
+fn read_list(tokens: &mut TokenState, end: &'static str) -> MalResult<MalSeq> {
+    let mut items = MalSeq::new();
     loop {
         let next = tokens.peek()?;
         if next == ")" || next == "]" || next == "}" {
@@ -81,25 +150,9 @@ fn read_list(tokens: &mut TokenState, end: &'static str) -> MalResult<MalType> {
                         format!("unbalanced parens: expected {}, got {}", end, next)));
             }
         }
-        items.push(read_form(tokens)?);
-    }
-    match end {
-        ")" => Ok(MalType::List(items)),
-        "]" => Ok(MalType::Vector(items)),
-        "}" => {
-            let mut m = HashMap::new();
-            for i in 0..items.len()/2 {
-                let k = items.get(i*2).unwrap().get_string().unwrap();
-                let v = items.get(i*2+1).unwrap();
-                m.insert(k, v.clone());
-            }
-
-            Ok(MalType::HashMap(MalHashMap {
-                map: m
-            }))
-        }
-        _ => panic!("unexpected ending passed to read_list()"),
+        items.push_back(read_form(tokens)?);
     }
+    Ok(items)
 }
 
 fn read_atom(t: &str) -> MalResult<MalType> {
@@ -113,16 +166,78 @@ fn read_atom(t: &str) -> MalResult<MalType> {
             _ => (),
         }
         let next = t.chars().next();
-        if next == Some('"') {
+        if let Some(pattern) = t.strip_prefix("#\"") {
+            parse_regex(pattern)
+        } else if next == Some('"') {
             parse_string(t)
+        } else if next == Some('\\') {
+            parse_char(t)
         } else if next == Some(':') {
-            Ok(MalType::Keyword(String::from(&t[1..])))
-        } else if next == Some(';') {
-            Err(MalError::Empty)
+            let name = String::from(&t[1..]);
+            KEYWORDS.with(|k| k.borrow_mut().insert(name.clone()));
+            Ok(MalType::Keyword(name.into()))
         } else {
-            Ok(MalType::Symbol(String::from(t)))
+            Ok(MalType::Symbol(MalSymbol::new(t)))
+        }
+    }
+}
+
+/// Names for characters that are awkward to write after a backslash. Needs to be kept up to date
+/// with the reverse operation in printer.rs
+pub const CHAR_NAMES: &[(&str, char)] = &[
+    ("newline", '\n'),
+    ("space", ' '),
+    ("tab", '\t'),
+    ("return", '\r'),
+    ("backspace", '\u{8}'),
+    ("formfeed", '\u{c}'),
+];
+
+fn parse_char(t: &str) -> MalResult<MalType> {
+    let name = &t[1..];
+    let mut chars = name.chars();
+    if let (Some(c), None) = (chars.next(), chars.next()) {
+        return Ok(MalType::Char(c));
+    }
+    if let Some(&(_, c)) = CHAR_NAMES.iter().find(|&&(n, _)| n == name) {
+        return Ok(MalType::Char(c));
+    }
+    // \uXXXX
+    if let Some(hex) = name.strip_prefix('u') {
+        if let Some(c) = u32::from_str_radix(hex, 16).ok().and_then(char::from_u32) {
+            return Ok(MalType::Char(c));
+        }
+    }
+    Err(MalError::ParseError(format!("unknown character literal: {}", t)))
+}
+
+/// Reads the pattern of a `#"..."` regex literal, given the text after `#"`. Backslashes are
+/// passed through to the regex engine, except that `\"` stands for a quote.
+fn parse_regex(t: &str) -> MalResult<MalType> {
+    let unterminated = || MalError::Incomplete(
+        format!("unexpected EOF: unterminated regex: #\"{}", t));
+    let mut pattern = String::with_capacity(t.len());
+    let mut chars = t.chars();
+    loop {
+        match chars.next() {
+            Some('"') => break,
+            Some('\\') => match chars.next() {
+                Some('"') => pattern.push('"'),
+                Some(c) => {
+                    pattern.push('\\');
+                    pattern.push(c);
+                }
+                None => return Err(unterminated()),
+            },
+            Some(c) => pattern.push(c),
+            None => return Err(unterminated()),
         }
     }
+    if chars.next().is_some() {
+        return Err(MalError::ParseError(format!("invalid regex literal: #\"{}", t)));
+    }
+    MalRegex::new(&pattern).map(MalType::Regex)
+        .map_err(|e| MalError::ParseError(format!("invalid regex: {}", e)))
 }
 
 fn parse_string(t: &str) -> MalResult<MalType> {
@@ -163,14 +278,15 @@ fn parse_string(t: &str) -> MalResult<MalType> {
         }
     }
 
-    if escaped {
-        return Err(MalError::ParseError(
-            format!("unexpected EOF: dangling escape backslash: {}", t)));
+    if escaped || quotes == 1 {
+        // The string runs past the end of the input.
+        return Err(MalError::Incomplete(
+            format!("unexpected EOF: unterminated string: {}", t)));
     }
     if quotes != 2 {
         return Err(MalError::ParseError(
-            format!("unexpected EOF: too many or two few quotes ({}): {}", quotes, t)));
+            format!("unbalanced quotes ({}): {}", quotes, t)));
     }
 
-    Ok(MalType::Str(s))
+    Ok(MalType::Str(s.into()))
 }
@@ -0,0 +1,263 @@
+use std::borrow::Cow;
+use std::cell::{Cell, RefCell};
+use std::env;
+use std::io;
+use std::io::IsTerminal;
+use std::path::PathBuf;
+
+use rustyline::completion::Completer;
+use rustyline::error::ReadlineError;
+use rustyline::highlight::{CmdKind, Highlighter};
+use rustyline::hint::Hinter;
+use rustyline::history::DefaultHistory;
+use rustyline::validate::Validator;
+use rustyline::{Context, Editor, Helper};
+
+use super::reader::{seen_keywords, token_spans};
+
+pub const PROMPT: &str = "user> ";
+
+/// Shown instead of `PROMPT` while the reader is waiting for the rest of an unfinished form.
+pub const CONTINUATION_PROMPT: &str = "  ... ";
+
+const HISTORY_FILE: &str = ".mal-history";
+
+type MalEditor = Editor<MalHelper, DefaultHistory>;
+
+thread_local! {
+    // Shared by the REPL and the `readline` builtin so that both see the same history.
+    static EDITOR: RefCell<Option<MalEditor>> = const { RefCell::new(None) };
+}
+
+const STRING_COLOR: &str = "32";
+const KEYWORD_COLOR: &str = "35";
+const NUMBER_COLOR: &str = "36";
+const COMMENT_COLOR: &str = "90";
+const SPECIAL_FORM_COLOR: &str = "1;33";
+const MATCHING_DELIMITER_COLOR: &str = "1;34";
+
+/// Tab-completes symbols from the words set by `set_completions` and keywords from the reader,
+/// and colourizes the line being edited.
+struct MalHelper {
+    symbols: Vec<String>,
+    special_forms: Vec<String>,
+    color: bool,
+    // Cleared for the final redraw of a line so the matching delimiter doesn't stay highlighted.
+    show_match: Cell<bool>,
+}
+
+impl MalHelper {
+    fn new() -> MalHelper {
+        MalHelper {
+            symbols: Vec::new(),
+            special_forms: Vec::new(),
+            color: use_color(),
+            show_match: Cell::new(true),
+        }
+    }
+
+    fn token_color(&self, tok: &str) -> Option<&'static str> {
+        let first = tok.chars().next()?;
+        if first == '"' || first == '\\' || tok.starts_with("#\"") {
+            Some(STRING_COLOR)
+        } else if first == ':' {
+            Some(KEYWORD_COLOR)
+        } else if first == ';' {
+            Some(COMMENT_COLOR)
+        } else if tok.parse::<i32>().is_ok() {
+            Some(NUMBER_COLOR)
+        } else if self.special_forms.iter().any(|f| f == tok) {
+            Some(SPECIAL_FORM_COLOR)
+        } else {
+            None
+        }
+    }
+}
+
+/// Colour is only used on a terminal, and never when `NO_COLOR` is set.
+fn use_color() -> bool {
+    io::stdout().is_terminal()
+        && env::var_os("NO_COLOR").is_none_or(|v| v.is_empty())
+        && env::var("TERM").map_or(true, |t| t != "dumb")
+}
+
+/// Returns the offsets of the delimiter under or just before the cursor and its partner.
+fn matching_delimiters(tokens: &[(usize, &str)], pos: usize) -> Option<(usize, usize)> {
+    let mut open = Vec::new();
+    let mut pairs = Vec::new();
+    for &(start, tok) in tokens {
+        match tok {
+            "(" | "[" | "{" | "#{" => open.push(start),
+            ")" | "]" | "}" => {
+                if let Some(o) = open.pop() {
+                    pairs.push((o, start));
+                }
+            }
+            _ => (),
+        }
+    }
+
+    let at = |p: usize| pairs.iter().find(|&&(o, c)| o == p || c == p).copied();
+    at(pos).or_else(|| pos.checked_sub(1).and_then(at))
+}
+
+fn is_delimiter(c: char) -> bool {
+    c.is_whitespace() || "()[]{}'\"`,;~@^".contains(c)
+}
+
+impl Completer for MalHelper {
+    type Candidate = String;
+
+    fn complete(&self, line: &str, pos: usize, _ctx: &Context<'_>)
+        -> rustyline::Result<(usize, Vec<String>)> {
+        let start = line[..pos].rfind(is_delimiter).map_or(0, |i| i + 1);
+        let prefix = &line[start..pos];
+        if prefix.is_empty() {
+            return Ok((pos, Vec::new()));
+        }
+
+        let mut candidates: Vec<String> = if let Some(prefix) = prefix.strip_prefix(':') {
+            seen_keywords().into_iter()
+                .filter(|k| k.starts_with(prefix))
+                .map(|k| format!(":{}", k))
+                .collect()
+        } else {
+            self.symbols.iter().chain(self.special_forms.iter())
+                .filter(|s| s.starts_with(prefix))
+                .cloned()
+                .collect()
+        };
+        candidates.sort();
+        candidates.dedup();
+        Ok((start, candidates))
+    }
+}
+
+impl Hinter for MalHelper {
+    type Hint = String;
+}
+
+impl Highlighter for MalHelper {
+    fn highlight<'l>(&self, line: &'l str, pos: usize) -> Cow<'l, str> {
+        if !self.color {
+            return Cow::Borrowed(line);
+        }
+
+        let tokens = token_spans(line);
+        let matching = if self.show_match.get() {
+            matching_delimiters(&tokens, pos)
+        } else {
+            None
+        };
+
+        let mut acc = String::with_capacity(line.len() * 2);
+        let mut last = 0;
+        for (start, tok) in tokens {
+            acc.push_str(&line[last..start]);
+            let color = match matching {
+                Some((o, c)) if start == o || start == c => Some(MATCHING_DELIMITER_COLOR),
+                _ => self.token_color(tok),
+            };
+            if let Some(color) = color {
+                acc.push_str(&format!("\x1b[{}m{}\x1b[0m", color, tok));
+            } else {
+                acc.push_str(tok);
+            }
+            last = start + tok.len();
+        }
+        acc.push_str(&line[last..]);
+        Cow::Owned(acc)
+    }
+
+    fn highlight_char(&self, _line: &str, _pos: usize, kind: CmdKind) -> bool {
+        self.show_match.set(kind != CmdKind::ForcedRefresh);
+        self.color
+    }
+}
+
+impl Validator for MalHelper {}
+
+impl Helper for MalHelper {}
+
+/// Whether a person is typing at the REPL, as opposed to a pipe or the test harness (which sets
+/// `TERM=dumb`). Unfinished forms are only continued on the next line in interactive mode;
+/// otherwise they are reported as errors.
+pub fn is_interactive() -> bool {
+    io::stdin().is_terminal() && env::var("TERM").map_or(true, |t| t != "dumb")
+}
+
+fn history_path() -> Option<PathBuf> {
+    env::var_os("HOME").map(|home| PathBuf::from(home).join(HISTORY_FILE))
+}
+
+fn new_editor() -> MalEditor {
+    let mut editor = MalEditor::new().expect("error creating line editor");
+    editor.set_helper(Some(MalHelper::new()));
+    if is_interactive() {
+        if let Some(path) = history_path() {
+            // A missing history file just means this is the first session.
+            editor.load_history(&path).ok();
+        }
+    }
+    editor
+}
+
+fn with_helper(f: impl FnOnce(&mut MalHelper)) {
+    EDITOR.with(|cell| {
+        let mut cell = cell.borrow_mut();
+        let editor = cell.get_or_insert_with(new_editor);
+        if let Some(helper) = editor.helper_mut() {
+            f(helper);
+        }
+    })
+}
+
+/// Sets the symbols offered by tab completion, typically everything bound in the REPL
+/// environment.
+pub fn set_completions(symbols: Vec<String>) {
+    with_helper(|helper| helper.symbols = symbols);
+}
+
+/// Sets the special forms of the current step, which are completed and highlighted.
+pub fn set_special_forms(forms: &[&str]) {
+    with_helper(|helper| helper.special_forms = forms.iter().map(|f| f.to_string()).collect());
+}
+
+/// What `readline` got from the user.
+pub enum Line {
+    /// A line, without its newline.
+    Read(String),
+    /// The user pressed Ctrl-C, to abandon what they were typing.
+    Interrupted,
+    /// The input has ended, or can't be read.
+    Eof,
+}
+
+/// Prints `prompt` and reads one line, with line editing and history when attached to a
+/// terminal.
+///
+/// An error reading the input is reported on stderr and treated as the end of it.
+pub fn readline(prompt: &str) -> Line {
+    EDITOR.with(|cell| {
+        let mut cell = cell.borrow_mut();
+        let editor = cell.get_or_insert_with(new_editor);
+
+        match editor.readline(prompt) {
+            Ok(line) => {
+                if is_interactive() && !line.trim().is_empty() {
+                    editor.add_history_entry(line.as_str()).ok();
+                    if let Some(path) = history_path() {
+                        editor.save_history(&path).ok();
+                    }
+                }
+                Line::Read(line)
+            }
+            Err(ReadlineError::Interrupted) => Line::Interrupted,
+            Err(ReadlineError::Eof) => Line::Eof,
+            Err(e) => {
+                eprintln!("error reading line: {}", e);
+                Line::Eof
+            }
+        }
+    })
+}
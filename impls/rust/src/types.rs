@@ -1,47 +1,931 @@
-use std::collections::HashMap;
+use std::cell::RefCell;
+use std::cmp::Ordering;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap as StdHashMap;
+use std::convert::TryFrom;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::ops::Bound;
+use std::rc::Rc;
 
+use im_rc::{vector, HashMap, HashSet, OrdMap, OrdSet, Vector};
+use regex::Regex;
+
+use super::core::invalid_args;
+use super::env::{MalEnv, WeakEnv};
+use super::eval::{apply_closure, destructure};
+use super::printer::pr_str;
+
+/// Arguments to a function.
 pub type MalList = Vec<MalType>;
 
-#[derive(Debug, Clone)]
+/// The elements of a list or vector. This is a persistent vector, so cloning it is cheap and
+/// updated copies share structure with the original.
+pub type MalSeq = Vector<MalType>;
+
+/// A builtin function implemented in Rust.
+pub type MalFn = fn(MalList) -> MalResult<MalType>;
+
+/// A builtin function that also needs the environment it was defined in, such as `eval`.
+pub type MalEnvFn = fn(&MalEnv, MalList) -> MalResult<MalType>;
+
+/// The names of the special forms, in the order of their `MalSymbol` constants.
+pub const SPECIAL_FORM_NAMES: [&str; 12] = [
+    "def!", "defmacro!", "let*", "do", "if", "fn*", "loop", "recur", "quote", "lazy-seq",
+    "quasiquote", "try*",
+];
+
+/// Other symbols the evaluator recognizes inside special forms, in the order of their
+/// `MalSymbol` constants after the special forms'.
+const SYNTAX_NAMES: [&str; 4] = ["&", "catch*", "unquote", "splice-unquote"];
+
+/// Every symbol name seen so far, indexed by `MalSymbol` id.
+struct Interner {
+    names: Vec<Rc<str>>,
+    ids: StdHashMap<Rc<str>, u32>,
+}
+
+impl Interner {
+    // The special forms and other syntax are interned first, so that their ids are the constants
+    // on `MalSymbol` and the evaluator can dispatch on them without looking up names.
+    fn new() -> Interner {
+        let mut interner = Interner { names: Vec::new(), ids: StdHashMap::new() };
+        for name in SPECIAL_FORM_NAMES.iter().chain(&SYNTAX_NAMES) {
+            interner.intern(name);
+        }
+        interner
+    }
+
+    fn intern(&mut self, name: &str) -> u32 {
+        if let Some(&id) = self.ids.get(name) {
+            return id;
+        }
+        let id = u32::try_from(self.names.len()).expect("too many symbols");
+        let name: Rc<str> = name.into();
+        self.names.push(name.clone());
+        self.ids.insert(name, id);
+        id
+    }
+}
+
+thread_local! {
+    static SYMBOLS: RefCell<Interner> = RefCell::new(Interner::new());
+}
+
+/// An interned symbol name. Symbols with the same name share an id, so they compare and hash
+/// as integers.
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub struct MalSymbol(u32);
+
+impl MalSymbol {
+    pub const DEF: MalSymbol = MalSymbol(0);
+    pub const DEFMACRO: MalSymbol = MalSymbol(1);
+    pub const LET: MalSymbol = MalSymbol(2);
+    pub const DO: MalSymbol = MalSymbol(3);
+    pub const IF: MalSymbol = MalSymbol(4);
+    pub const FN: MalSymbol = MalSymbol(5);
+    pub const LOOP: MalSymbol = MalSymbol(6);
+    pub const RECUR: MalSymbol = MalSymbol(7);
+    pub const QUOTE: MalSymbol = MalSymbol(8);
+    pub const LAZY_SEQ: MalSymbol = MalSymbol(9);
+    pub const QUASIQUOTE: MalSymbol = MalSymbol(10);
+    pub const TRY: MalSymbol = MalSymbol(11);
+    pub const AMP: MalSymbol = MalSymbol(12);
+    pub const CATCH: MalSymbol = MalSymbol(13);
+    pub const UNQUOTE: MalSymbol = MalSymbol(14);
+    pub const SPLICE_UNQUOTE: MalSymbol = MalSymbol(15);
+
+    pub fn new(name: &str) -> MalSymbol {
+        SYMBOLS.with(|table| MalSymbol(table.borrow_mut().intern(name)))
+    }
+
+    pub fn name(&self) -> Rc<str> {
+        SYMBOLS.with(|table| table.borrow().names[self.0 as usize].clone())
+    }
+}
+
+impl From<&str> for MalSymbol {
+    fn from(name: &str) -> MalSymbol {
+        MalSymbol::new(name)
+    }
+}
+
+impl From<String> for MalSymbol {
+    fn from(name: String) -> MalSymbol {
+        MalSymbol::new(&name)
+    }
+}
+
+impl fmt::Debug for MalSymbol {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?}", self.name())
+    }
+}
+
+impl fmt::Display for MalSymbol {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.name())
+    }
+}
+
+/// Symbols sort by name, not by id, so the order doesn't depend on when they were interned.
+impl Ord for MalSymbol {
+    fn cmp(&self, other: &MalSymbol) -> Ordering {
+        if self == other {
+            Ordering::Equal
+        } else {
+            self.name().cmp(&other.name())
+        }
+    }
+}
+
+impl PartialOrd for MalSymbol {
+    fn partial_cmp(&self, other: &MalSymbol) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct MalHashMap {
-    pub map: HashMap<String, MalType>,
+    pub map: HashMap<MalType, MalType>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MalSet {
+    pub set: HashSet<MalType>,
+}
+
+/// A key in a sorted collection, ordered by the collection's comparator function, or by the
+/// natural order on `MalType` when there is none.
+#[derive(Debug, Clone)]
+pub struct SortKey {
+    pub key: MalType,
+    comparator: Option<Rc<MalType>>,
+}
+
+thread_local! {
+    // The first error raised by a comparator during a tree operation. `Ord` can't fail, so
+    // `SortKey::cmp` stashes the error here and the sorted collection methods pick it up.
+    static COMPARE_ERROR: RefCell<Option<MalError>> = const { RefCell::new(None) };
+}
+
+/// Runs a tree operation, failing if a comparator raised an error during it.
+fn checked<T>(f: impl FnOnce() -> T) -> MalResult<T> {
+    COMPARE_ERROR.with(|e| e.borrow_mut().take());
+    let result = f();
+    match COMPARE_ERROR.with(|e| e.borrow_mut().take()) {
+        Some(e) => Err(e),
+        None => Ok(result),
+    }
+}
+
+/// Compares `a` and `b` with a comparator function, which may return a number (negative, zero
+/// or positive) or, like `<`, a boolean meaning "less than".
+pub fn compare_with(comparator: &MalType, a: &MalType, b: &MalType) -> MalResult<Ordering> {
+    match comparator.apply(vec![a.clone(), b.clone()])? {
+        MalType::Int(i) => Ok(i.cmp(&0)),
+        MalType::Bool(true) => Ok(Ordering::Less),
+        MalType::Bool(false) | MalType::Nil => {
+            match comparator.apply(vec![b.clone(), a.clone()])? {
+                MalType::Bool(true) => Ok(Ordering::Greater),
+                _ => Ok(Ordering::Equal),
+            }
+        }
+        other => Err(MalError::RuntimeError(
+                format!("comparator returned {}, expected a number or boolean", other))),
+    }
+}
+
+impl SortKey {
+    fn new(key: MalType, comparator: &Option<Rc<MalType>>) -> SortKey {
+        SortKey { key, comparator: comparator.clone() }
+    }
+}
+
+impl Ord for SortKey {
+    fn cmp(&self, other: &SortKey) -> Ordering {
+        match &self.comparator {
+            None => self.key.cmp(&other.key),
+            Some(f) => compare_with(f, &self.key, &other.key).unwrap_or_else(|e| {
+                COMPARE_ERROR.with(|c| c.borrow_mut().get_or_insert(e).clone());
+                Ordering::Equal
+            }),
+        }
+    }
+}
+
+impl PartialOrd for SortKey {
+    fn partial_cmp(&self, other: &SortKey) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for SortKey {
+    fn eq(&self, other: &SortKey) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for SortKey {}
+
+/// Builds the range bounds for `subseq`, given tests named `>`, `>=`, `<` or `<=`.
+fn range_bounds(tests: &[(&str, SortKey)]) -> MalResult<(Bound<SortKey>, Bound<SortKey>)> {
+    let mut start = Bound::Unbounded;
+    let mut end = Bound::Unbounded;
+    for (test, key) in tests {
+        match *test {
+            ">" => start = Bound::Excluded(key.clone()),
+            ">=" => start = Bound::Included(key.clone()),
+            "<" => end = Bound::Excluded(key.clone()),
+            "<=" => end = Bound::Included(key.clone()),
+            _ => return Err(MalError::RuntimeError(
+                    format!("subseq test must be one of < <= > >=, got {}", test))),
+        }
+    }
+    Ok((start, end))
+}
+
+/// A map whose entries are kept in key order.
+#[derive(Debug, Clone)]
+pub struct MalSortedMap {
+    pub comparator: Option<Rc<MalType>>,
+    pub map: OrdMap<SortKey, MalType>,
+}
+
+impl MalSortedMap {
+    pub fn new(comparator: Option<Rc<MalType>>) -> MalSortedMap {
+        MalSortedMap { comparator, map: OrdMap::new() }
+    }
+
+    pub fn insert(&mut self, key: MalType, val: MalType) -> MalResult<()> {
+        let key = SortKey::new(key, &self.comparator);
+        checked(|| self.map.insert(key, val)).map(|_| ())
+    }
+
+    pub fn remove(&mut self, key: &MalType) -> MalResult<()> {
+        let key = SortKey::new(key.clone(), &self.comparator);
+        checked(|| self.map.remove(&key)).map(|_| ())
+    }
+
+    pub fn get(&self, key: &MalType) -> MalResult<Option<MalType>> {
+        let key = SortKey::new(key.clone(), &self.comparator);
+        checked(|| self.map.get(&key).cloned())
+    }
+
+    /// Entries within the bounds given by `tests`, as `[key value]` vectors in key order.
+    pub fn range(&self, tests: &[(&str, MalType)]) -> MalResult<MalList> {
+        let tests: Vec<_> = tests.iter()
+            .map(|(t, k)| (*t, SortKey::new(k.clone(), &self.comparator)))
+            .collect();
+        let bounds = range_bounds(&tests)?;
+        checked(|| self.map.range(bounds)
+            .map(|(k, v)| MalType::Vector(vector![k.key.clone(), v.clone()]))
+            .collect())
+    }
+}
+
+/// A set whose elements are kept in order.
+#[derive(Debug, Clone)]
+pub struct MalSortedSet {
+    pub comparator: Option<Rc<MalType>>,
+    pub set: OrdSet<SortKey>,
+}
+
+impl MalSortedSet {
+    pub fn new(comparator: Option<Rc<MalType>>) -> MalSortedSet {
+        MalSortedSet { comparator, set: OrdSet::new() }
+    }
+
+    pub fn insert(&mut self, item: MalType) -> MalResult<()> {
+        let item = SortKey::new(item, &self.comparator);
+        checked(|| self.set.insert(item)).map(|_| ())
+    }
+
+    pub fn remove(&mut self, item: &MalType) -> MalResult<()> {
+        let item = SortKey::new(item.clone(), &self.comparator);
+        checked(|| self.set.remove(&item)).map(|_| ())
+    }
+
+    pub fn contains(&self, item: &MalType) -> MalResult<bool> {
+        let item = SortKey::new(item.clone(), &self.comparator);
+        checked(|| self.set.contains(&item))
+    }
+
+    /// Elements within the bounds given by `tests`, in order.
+    pub fn range(&self, tests: &[(&str, MalType)]) -> MalResult<MalList> {
+        let tests: Vec<_> = tests.iter()
+            .map(|(t, k)| (*t, SortKey::new(k.clone(), &self.comparator)))
+            .collect();
+        let bounds = range_bounds(&tests)?;
+        checked(|| self.set.range(bounds).map(|k| k.key.clone()).collect())
+    }
+}
+
+/// A mutable reference cell. Clones share the same cell.
+#[derive(Clone)]
+pub struct MalAtom(pub Rc<RefCell<MalType>>);
+
+impl MalAtom {
+    pub fn new(val: MalType) -> MalAtom {
+        MalAtom(Rc::new(RefCell::new(val)))
+    }
+}
+
+// Atoms can contain themselves, so this goes through the cycle-safe printer rather than
+// recursing into the contents.
+impl fmt::Debug for MalAtom {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", MalType::Atom(self.clone()))
+    }
 }
 
+/// One body of a function, with the parameters it accepts.
+#[derive(Debug, Clone)]
+pub struct MalArity {
+    // Binding forms for the fixed arguments, which may destructure them.
+    pub params: MalSeq,
+    // Binding form for the remaining arguments as a list, if the body takes `& rest`.
+    pub rest: Option<MalType>,
+    pub body: MalSeq,
+}
+
+impl MalArity {
+    fn accepts(&self, n: usize) -> bool {
+        n == self.params.len() || (self.rest.is_some() && n > self.params.len())
+    }
+
+    /// The number of arguments a `recur` to this body passes: one for each parameter,
+    /// including `& rest`.
+    pub fn recur_args(&self) -> usize {
+        self.params.len() + usize::from(self.rest.is_some())
+    }
+}
+
+/// A function defined in mal with `fn*`. Macros defined with `defmacro!` are closures that are
+/// called with their arguments unevaluated.
+#[derive(Clone)]
+pub struct MalClosure {
+    // Given by `(fn* name ...)`, or by the `def!` that first binds the closure.
+    pub name: Option<MalSymbol>,
+    // Whether `name` came from `fn*`, so the bodies can call the closure by it.
+    pub binds_name: bool,
+    pub arities: Vec<MalArity>,
+    pub env: MalEnv,
+    pub is_macro: bool,
+}
+
+impl MalClosure {
+    /// Returns the environment a call's parameters are bound in front of: the closure's own,
+    /// with a name given by `fn*` bound to the closure. The name is bound afresh for each call,
+    /// as capturing it would make the closure own a reference to itself.
+    pub fn scope(self: &Rc<Self>) -> MalEnv {
+        match self.name {
+            Some(name) if self.binds_name => {
+                let scope = MalEnv::new(Some(&self.env));
+                scope.set(name, MalType::Closure(self.clone()));
+                scope
+            }
+            _ => self.env.clone(),
+        }
+    }
+
+    /// Picks the body that accepts `args` and binds them to its parameters in a new environment
+    /// enclosed by `scope`. A body with a fixed arity is preferred over `& rest`.
+    pub fn bind(&self, scope: &MalEnv, args: MalList) -> MalResult<(MalEnv, &MalArity)> {
+        let n = args.len();
+        let arity = self.arities.iter().find(|a| a.rest.is_none() && a.accepts(n))
+            .or_else(|| self.arities.iter().find(|a| a.accepts(n)))
+            .ok_or_else(|| self.arity_error(n))?;
+        let env = MalEnv::new(Some(scope));
+        let mut args = args.into_iter();
+        for (param, arg) in arity.params.iter().zip(&mut args) {
+            destructure(param, arg, &env)?;
+        }
+        if let Some(rest) = &arity.rest {
+            destructure(rest, MalType::List(args.collect()), &env)?;
+        }
+        Ok((env, arity))
+    }
+
+    fn arity_error(&self, n: usize) -> MalError {
+        let name = self.name.map_or_else(|| "fn*".into(), |s| s.name());
+        let mut counts: Vec<_> = self.arities.iter().map(|a| match a.rest {
+            Some(_) => format!("{} or more", a.params.len()),
+            None => a.params.len().to_string(),
+        }).collect();
+        let mut expected = counts.pop().unwrap_or_default();
+        if !counts.is_empty() {
+            expected = format!("{} or {}", counts.join(", "), expected);
+        }
+        MalError::RuntimeError(
+            format!("({}) called with {} arguments, expects {}", name, n, expected))
+    }
+
+    /// Calls the closure, evaluating its body in the bound environment.
+    pub fn apply(self: &Rc<Self>, args: MalList) -> MalResult<MalType> {
+        apply_closure(self, args)
+    }
+}
+
+// Closures usually capture an environment that contains them, so the environment isn't shown.
+impl fmt::Debug for MalClosure {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(if self.is_macro { "#<macro" } else { "#<fn" })?;
+        if let Some(name) = self.name {
+            write!(f, " {}", name)?;
+        }
+        for arity in &self.arities {
+            let mut params = arity.params.clone();
+            if let Some(rest) = &arity.rest {
+                params.push_back(MalType::Symbol(MalSymbol::AMP));
+                params.push_back(rest.clone());
+            }
+            write!(f, " {}", MalType::Vector(params))?;
+        }
+        f.write_str(">")
+    }
+}
+
+/// A compiled regular expression, written `#"pattern"`.
+#[derive(Debug, Clone)]
+pub struct MalRegex {
+    pub regex: Regex,
+    // The same pattern anchored at both ends, for matching whole strings.
+    pub whole: Regex,
+}
+
+impl MalRegex {
+    pub fn new(pattern: &str) -> Result<MalRegex, regex::Error> {
+        Ok(MalRegex {
+            regex: Regex::new(pattern)?,
+            whole: Regex::new(&format!("^(?:{})$", pattern))?,
+        })
+    }
+
+    pub fn as_str(&self) -> &str {
+        self.regex.as_str()
+    }
+}
+
+/// Produces the contents of a lazy seq: nil, any other seqable value, or another lazy seq.
+pub type MalThunk = Box<dyn FnOnce() -> MalResult<MalType>>;
+
+enum LazyState {
+    Pending(MalThunk),
+    Realizing,
+    // The first element and the rest of the seq, or `None` if the seq is empty.
+    Realized(Option<(MalType, MalType)>),
+    Failed(MalError),
+}
+
+/// A sequence whose contents are computed when first needed and then remembered. Clones share
+/// the same memoized contents.
+#[derive(Clone)]
+pub struct MalLazySeq(Rc<RefCell<LazyState>>);
+
+impl MalLazySeq {
+    pub fn new(thunk: impl FnOnce() -> MalResult<MalType> + 'static) -> MalLazySeq {
+        MalLazySeq(Rc::new(RefCell::new(LazyState::Pending(Box::new(thunk)))))
+    }
+
+    /// An already realized seq of `first` followed by the seqable `rest`, which is left
+    /// unrealized.
+    pub fn cons(first: MalType, rest: MalType) -> MalLazySeq {
+        MalLazySeq(Rc::new(RefCell::new(LazyState::Realized(Some((first, rest))))))
+    }
+
+    /// Computes the contents of this seq if that hasn't happened yet, returning its first element
+    /// and the rest of it.
+    ///
+    /// A thunk that returns another unrealized lazy seq is realized in the same loop rather than
+    /// by recursing, so long chains of `lazy-seq`s need constant stack.
+    pub fn realize(&self) -> MalResult<Option<(MalType, MalType)>> {
+        let mut waiting = Vec::new();
+        let mut seq = self.clone();
+        let result = loop {
+            let state = std::mem::replace(&mut *seq.0.borrow_mut(), LazyState::Realizing);
+            let thunk = match state {
+                LazyState::Pending(thunk) => thunk,
+                LazyState::Realizing => {
+                    break Err(MalError::RuntimeError(
+                            String::from("lazy seq depends on its own contents")));
+                }
+                done => {
+                    let result = match &done {
+                        LazyState::Realized(cell) => Ok(cell.clone()),
+                        LazyState::Failed(e) => Err(e.clone()),
+                        _ => unreachable!(),
+                    };
+                    *seq.0.borrow_mut() = done;
+                    break result;
+                }
+            };
+            waiting.push(seq.clone());
+            match thunk() {
+                Ok(MalType::LazySeq(next)) => seq = next,
+                Ok(val) => break val.first_rest(),
+                Err(e) => break Err(e),
+            }
+        };
+        for seq in waiting {
+            *seq.0.borrow_mut() = match &result {
+                Ok(cell) => LazyState::Realized(cell.clone()),
+                Err(e) => LazyState::Failed(e.clone()),
+            };
+        }
+        result
+    }
+
+    // Detaches and returns the rest of this seq if nothing else refers to this cell.
+    fn take_rest_if_unique(&self) -> Option<MalLazySeq> {
+        if Rc::strong_count(&self.0) != 1 {
+            return None;
+        }
+        match &mut *self.0.try_borrow_mut().ok()? {
+            LazyState::Realized(Some((_, rest))) => match std::mem::replace(rest, MalType::Nil) {
+                MalType::LazySeq(next) => Some(next),
+                other => {
+                    *rest = other;
+                    None
+                }
+            },
+            _ => None,
+        }
+    }
+}
+
+// Dropping a long realized seq would otherwise recurse once per element.
+impl Drop for MalLazySeq {
+    fn drop(&mut self) {
+        let mut next = self.take_rest_if_unique();
+        while let Some(seq) = next {
+            next = seq.take_rest_if_unique();
+        }
+    }
+}
+
+// Printing a lazy seq could realize an infinite sequence, so only say whether it is realized.
+impl fmt::Debug for MalLazySeq {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.0.try_borrow().as_deref() {
+            Ok(LazyState::Realized(Some((first, _)))) => write!(f, "LazySeq({:?} ...)", first),
+            Ok(LazyState::Realized(None)) => f.write_str("LazySeq()"),
+            _ => f.write_str("LazySeq(...)"),
+        }
+    }
+}
+
+/// Iterates over the elements of any seqable value, realizing lazy seqs as it goes.
+pub struct SeqIter {
+    rest: MalType,
+}
+
+impl Iterator for SeqIter {
+    type Item = MalResult<MalType>;
+
+    fn next(&mut self) -> Option<MalResult<MalType>> {
+        match self.rest.first_rest() {
+            Ok(Some((first, rest))) => {
+                self.rest = rest;
+                Some(Ok(first))
+            }
+            Ok(None) => None,
+            Err(e) => {
+                self.rest = MalType::Nil;
+                Some(Err(e))
+            }
+        }
+    }
+}
+
+/// A mal value. Strings are reference counted and collections are persistent, so cloning a value
+/// never copies its contents.
 #[derive(Debug, Clone)]
 pub enum MalType {
     Nil,
     Bool(bool),
     Int(i32),
-    Keyword(String),
-    Symbol(String),
-    Str(String),
-    List(MalList),
-    Vector(MalList),
+    Char(char),
+    Keyword(Rc<str>),
+    Symbol(MalSymbol),
+    Str(Rc<str>),
+    List(MalSeq),
+    Vector(MalSeq),
     HashMap(MalHashMap),
-    Fun(fn(MalList) -> MalResult<MalType>),
+    Set(MalSet),
+    SortedMap(MalSortedMap),
+    SortedSet(MalSortedSet),
+    Fun(&'static str, MalFn), // builtins carry their name for printing
+    Macro(&'static str, MalFn), // expands its unevaluated args into a form to evaluate
+    EnvFun(&'static str, MalEnvFn, WeakEnv), // a builtin given the environment it was bound in
+    Closure(Rc<MalClosure>),
+    Atom(MalAtom),
+    LazySeq(MalLazySeq),
+    Regex(MalRegex),
 }
 
 impl MalType {
     pub fn is_list(&self) -> bool {
-        if let MalType::List(_) = *self {
-            true
-        } else {
-            false
+        matches!(*self, MalType::List(_))
+    }
+
+    /// Everything but `nil` and `false` counts as true.
+    pub fn is_truthy(&self) -> bool {
+        !matches!(self, MalType::Nil | MalType::Bool(false))
+    }
+
+    /// Whether `apply` can call this value: a function, or a keyword or collection used for
+    /// lookup.
+    pub fn is_callable(&self) -> bool {
+        match self {
+            MalType::Closure(c) => !c.is_macro,
+            _ => matches!(self, MalType::Fun(..) | MalType::EnvFun(..) | MalType::Keyword(_)
+                | MalType::HashMap(_)
+                | MalType::SortedMap(_) | MalType::Set(_) | MalType::SortedSet(_)
+                | MalType::Vector(_)),
         }
     }
 
+    /// Calls a function value with `args`. As in Clojure, keywords, maps, sets and vectors can
+    /// also be called to look things up:
+    /// - `(:k m)` and `(:k m default)` look `:k` up in `m`, as `get` does.
+    /// - `(m k)` and `(m k default)` look `k` up in the map `m`.
+    /// - `(s x)` returns `x` if it is in the set `s`, and nil otherwise.
+    /// - `(v i)` returns the element of `v` at index `i`, and is an error if there is none.
+    pub fn apply(&self, args: MalList) -> MalResult<MalType> {
+        let invalid = || invalid_args(&pr_str(self, true), &args);
+        let lookup = |coll: &MalType, key: &MalType, default: Option<&MalType>| {
+            let found = coll.get(key)?;
+            Ok(found.or_else(|| default.cloned()).unwrap_or(MalType::Nil))
+        };
+        match (self, args.as_slice()) {
+            (MalType::Fun(_, f), _) => f(args),
+            (MalType::EnvFun(name, f, env), _) => match env.upgrade() {
+                Some(env) => f(&env, args),
+                None => Err(MalError::RuntimeError(
+                    format!("({}) was called after its environment was dropped", name))),
+            },
+            (MalType::Closure(c), _) => c.apply(args),
+            (MalType::Keyword(_), [coll]) => lookup(coll, self, None),
+            (MalType::Keyword(_), [coll, default]) => lookup(coll, self, Some(default)),
+            (MalType::HashMap(_) | MalType::SortedMap(_), [key]) => lookup(self, key, None),
+            (MalType::HashMap(_) | MalType::SortedMap(_), [key, default]) => {
+                lookup(self, key, Some(default))
+            }
+            (MalType::Set(_) | MalType::SortedSet(_), [key]) => lookup(self, key, None),
+            (MalType::Vector(v), [MalType::Int(i)]) => {
+                usize::try_from(*i).ok().and_then(|i| v.get(i).cloned()).ok_or_else(|| {
+                    MalError::RuntimeError(format!("index {} is out of bounds for {}", i, self))
+                })
+            }
+            (MalType::Keyword(_) | MalType::HashMap(_) | MalType::SortedMap(_)
+                | MalType::Set(_) | MalType::SortedSet(_) | MalType::Vector(_), _) => {
+                Err(invalid())
+            }
+            _ => Err(MalError::RuntimeError(format!("{} is not a function", self))),
+        }
+    }
+
+    /// Looks `key` up as `get` does: the value for it in a map, the element equal to it in a set,
+    /// or the element at that index in a vector. Nil contains nothing, and anything else is an
+    /// error.
+    pub fn get(&self, key: &MalType) -> MalResult<Option<MalType>> {
+        Ok(match (self, key) {
+            (MalType::HashMap(m), _) => m.map.get(key).cloned(),
+            (MalType::SortedMap(m), _) => m.get(key)?,
+            (MalType::Set(s), _) => s.set.contains(key).then(|| key.clone()),
+            (MalType::SortedSet(s), _) => {
+                if s.contains(key)? { Some(key.clone()) } else { None }
+            }
+            (MalType::Vector(v), MalType::Int(i)) => {
+                usize::try_from(*i).ok().and_then(|i| v.get(i).cloned())
+            }
+            (MalType::Nil, _) => None,
+            _ => return Err(MalError::RuntimeError(
+                    format!("can't look up {} in {}", key, self))),
+        })
+    }
+
+    /// Splits a seqable value into its first element and the rest, or returns `None` if it is
+    /// empty. Strings are sequences of characters and maps are sequences of `[key value]`
+    /// vectors.
+    pub fn first_rest(&self) -> MalResult<Option<(MalType, MalType)>> {
+        let items: MalSeq = match self {
+            MalType::List(l) | MalType::Vector(l) => {
+                return Ok(l.front().map(|first| (first.clone(), MalType::List(l.skip(1)))));
+            }
+            MalType::LazySeq(seq) => return seq.realize(),
+            MalType::Nil => return Ok(None),
+            MalType::Str(s) => s.chars().map(MalType::Char).collect(),
+            MalType::Set(s) => s.set.iter().cloned().collect(),
+            MalType::SortedSet(s) => s.set.iter().map(|k| k.key.clone()).collect(),
+            MalType::HashMap(m) => m.map.iter()
+                .map(|(k, v)| MalType::Vector(vector![k.clone(), v.clone()]))
+                .collect(),
+            MalType::SortedMap(m) => m.map.iter()
+                .map(|(k, v)| MalType::Vector(vector![k.key.clone(), v.clone()]))
+                .collect(),
+            _ => return Err(MalError::RuntimeError(format!("{} is not a sequence", self))),
+        };
+        MalType::List(items).first_rest()
+    }
+
+    /// Iterates over the elements of a seqable value.
+    pub fn seq_iter(&self) -> SeqIter {
+        SeqIter { rest: self.clone() }
+    }
+
     // Returns the string from a String or Keyword
     pub fn get_string(&self) -> Option<String> {
         match self {
-            MalType::Str(s) | MalType::Keyword(s) => Some(s.clone()),
+            MalType::Str(s) | MalType::Keyword(s) => Some(s.to_string()),
             _ => None
         }
     }
 }
 
+/// Values compare structurally, except that atoms are only equal to themselves. As in other mal
+/// implementations, a list and a vector with equal elements are equal.
+impl PartialEq for MalType {
+    fn eq(&self, other: &MalType) -> bool {
+        use MalType::*;
+        match (self, other) {
+            (Nil, Nil) => true,
+            (Bool(a), Bool(b)) => a == b,
+            (Int(a), Int(b)) => a == b,
+            (Char(a), Char(b)) => a == b,
+            (Keyword(a), Keyword(b)) | (Str(a), Str(b)) => a == b,
+            (Symbol(a), Symbol(b)) => a == b,
+            (List(a), List(b)) | (List(a), Vector(b))
+                | (Vector(a), List(b)) | (Vector(a), Vector(b)) => a == b,
+            (HashMap(a), HashMap(b)) => a == b,
+            // Sorted collections are equal when their contents are, whatever their comparators,
+            // and can equal their hashed counterparts.
+            (HashMap(_), SortedMap(_)) | (SortedMap(_), HashMap(_))
+                | (SortedMap(_), SortedMap(_)) => {
+                sorted_entries(self) == sorted_entries(other)
+            }
+            (Set(a), Set(b)) => a == b,
+            (Set(_), SortedSet(_)) | (SortedSet(_), Set(_)) | (SortedSet(_), SortedSet(_)) => {
+                sorted_elements(self) == sorted_elements(other)
+            }
+            // Lazy seqs are realized as far as needed to tell; one that fails to realize is
+            // unequal to everything.
+            (LazySeq(_), List(_) | Vector(_) | LazySeq(_)) | (List(_) | Vector(_), LazySeq(_)) => {
+                let mut a = self.seq_iter();
+                let mut b = other.seq_iter();
+                loop {
+                    match (a.next(), b.next()) {
+                        (None, None) => return true,
+                        (Some(Ok(x)), Some(Ok(y))) if x == y => (),
+                        _ => return false,
+                    }
+                }
+            }
+            (Fun(a, _) | EnvFun(a, ..), Fun(b, _) | EnvFun(b, ..)) => a == b,
+            (Macro(a, _), Macro(b, _)) => a == b,
+            (Atom(a), Atom(b)) => Rc::ptr_eq(&a.0, &b.0),
+            (Closure(a), Closure(b)) => Rc::ptr_eq(a, b),
+            (Regex(a), Regex(b)) => a.as_str() == b.as_str(),
+            _ => false,
+        }
+    }
+}
+
+impl Eq for MalType {}
+
+/// Hashes the unordered entries of a map or set so that the result doesn't depend on iteration
+/// order.
+fn hash_unordered<T: Hash>(items: impl Iterator<Item = T>, state: &mut impl Hasher) {
+    let mut acc: u64 = 0;
+    for item in items {
+        let mut h = DefaultHasher::new();
+        item.hash(&mut h);
+        acc = acc.wrapping_add(h.finish());
+    }
+    acc.hash(state);
+}
+
+impl Hash for MalType {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        use MalType::*;
+        match self {
+            Nil => 0.hash(state),
+            Bool(b) => b.hash(state),
+            Int(i) => i.hash(state),
+            Char(c) => c.hash(state),
+            Keyword(s) => { 1.hash(state); s.hash(state) },
+            Symbol(s) => { 2.hash(state); s.hash(state) },
+            Str(s) => { 3.hash(state); s.hash(state) },
+            // Lists and vectors hash alike since they can be equal.
+            List(l) | Vector(l) => { 4.hash(state); l.hash(state) },
+            LazySeq(_) => {
+                4.hash(state);
+                self.seq_iter().map_while(Result::ok).for_each(|e| e.hash(state))
+            }
+            HashMap(m) => hash_unordered(m.map.iter(), state),
+            Set(s) => hash_unordered(s.set.iter(), state),
+            SortedMap(m) => hash_unordered(m.map.iter().map(|(k, v)| (&k.key, v)), state),
+            SortedSet(s) => hash_unordered(s.set.iter().map(|k| &k.key), state),
+            Fun(name, _) | EnvFun(name, ..) => name.hash(state),
+            Macro(name, _) => { 6.hash(state); name.hash(state) },
+            Atom(a) => Rc::as_ptr(&a.0).hash(state),
+            Closure(c) => Rc::as_ptr(c).hash(state),
+            Regex(r) => { 5.hash(state); r.as_str().hash(state) },
+        }
+    }
+}
+
+impl MalType {
+    // Position of each kind of value in the total order; values of different kinds sort by this.
+    fn rank(&self) -> u8 {
+        use MalType::*;
+        match self {
+            Nil => 0,
+            Bool(_) => 1,
+            Int(_) => 2,
+            Char(_) => 3,
+            Str(_) => 4,
+            Keyword(_) => 5,
+            Symbol(_) => 6,
+            List(_) | Vector(_) | LazySeq(_) => 7,
+            HashMap(_) | SortedMap(_) => 8,
+            Set(_) | SortedSet(_) => 9,
+            Fun(..) | EnvFun(..) => 10,
+            Atom(_) => 11,
+            Regex(_) => 12,
+            Macro(..) => 13,
+            Closure(_) => 14,
+        }
+    }
+}
+
+/// Entries of a map-like value in key order, for comparing maps.
+fn sorted_entries(val: &MalType) -> Vec<(&MalType, &MalType)> {
+    let mut acc: Vec<_> = match val {
+        MalType::HashMap(m) => m.map.iter().collect(),
+        MalType::SortedMap(m) => m.map.iter().map(|(k, v)| (&k.key, v)).collect(),
+        _ => Vec::new(),
+    };
+    acc.sort_by(|a, b| a.0.cmp(b.0));
+    acc
+}
+
+/// Elements of a set-like value in natural order, for comparing sets.
+fn sorted_elements(val: &MalType) -> Vec<&MalType> {
+    let mut acc: Vec<_> = match val {
+        MalType::Set(s) => s.set.iter().collect(),
+        MalType::SortedSet(s) => s.set.iter().map(|k| &k.key).collect(),
+        _ => Vec::new(),
+    };
+    acc.sort();
+    acc
+}
+
+/// A total order on values, consistent with `==`: values of different kinds are ordered by kind
+/// (nil, booleans, numbers, characters, strings, keywords, symbols, sequences, maps, sets,
+/// functions, atoms, regexes, macros, closures), sequences compare element by element, and maps
+/// and sets compare their sorted contents.
+impl Ord for MalType {
+    fn cmp(&self, other: &MalType) -> Ordering {
+        use MalType::*;
+        match (self, other) {
+            (Bool(a), Bool(b)) => a.cmp(b),
+            (Int(a), Int(b)) => a.cmp(b),
+            (Char(a), Char(b)) => a.cmp(b),
+            (Keyword(a), Keyword(b)) | (Str(a), Str(b)) => a.cmp(b),
+            (Symbol(a), Symbol(b)) => a.cmp(b),
+            (List(a), List(b)) | (List(a), Vector(b))
+                | (Vector(a), List(b)) | (Vector(a), Vector(b)) => a.cmp(b),
+            (HashMap(_) | SortedMap(_), HashMap(_) | SortedMap(_)) => {
+                sorted_entries(self).cmp(&sorted_entries(other))
+            }
+            (Set(_) | SortedSet(_), Set(_) | SortedSet(_)) => {
+                sorted_elements(self).cmp(&sorted_elements(other))
+            }
+            (LazySeq(_), List(_) | Vector(_) | LazySeq(_)) | (List(_) | Vector(_), LazySeq(_)) => {
+                self.seq_iter().map_while(Result::ok).cmp(other.seq_iter().map_while(Result::ok))
+            }
+            (Fun(a, _) | EnvFun(a, ..), Fun(b, _) | EnvFun(b, ..)) => a.cmp(b),
+            (Macro(a, _), Macro(b, _)) => a.cmp(b),
+            (Atom(a), Atom(b)) => Rc::as_ptr(&a.0).cmp(&Rc::as_ptr(&b.0)),
+            (Closure(a), Closure(b)) => Rc::as_ptr(a).cmp(&Rc::as_ptr(b)),
+            (Regex(a), Regex(b)) => a.as_str().cmp(b.as_str()),
+            _ => self.rank().cmp(&other.rank()),
+        }
+    }
+}
+
+impl PartialOrd for MalType {
+    fn partial_cmp(&self, other: &MalType) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
 #[derive(Debug, Clone)]
 pub enum MalError {
     Empty, // not an error; either comment or blank input
+    Incomplete(String), // input ended in the middle of a form; more lines may complete it
     NotFoundError,
     ParseError(String),
     RuntimeError(String),